@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX microblogs_author_idx;
+
+ALTER TABLE microblogs DROP COLUMN author;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+-- Existing posts predate authorship, so they are backfilled with a NULL
+-- author and rendered as unknown.
+ALTER TABLE microblogs
+    ADD COLUMN author VARCHAR(50) REFERENCES users (email) ON DELETE SET NULL;
+
+CREATE INDEX microblogs_author_idx ON microblogs (author);
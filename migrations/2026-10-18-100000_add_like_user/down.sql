@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE likes DROP CONSTRAINT likes_blog_id_fkey;
+
+ALTER TABLE likes DROP CONSTRAINT likes_blog_id_user_key;
+
+ALTER TABLE likes DROP COLUMN "user";
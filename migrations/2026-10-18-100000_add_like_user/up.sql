@@ -0,0 +1,15 @@
+-- Your SQL goes here
+
+-- Likes created before per-user tracking have no owner and keep a NULL user.
+ALTER TABLE likes
+    ADD COLUMN "user" VARCHAR(50) REFERENCES users (email) ON DELETE CASCADE;
+
+ALTER TABLE likes
+    ADD CONSTRAINT likes_blog_id_user_key UNIQUE (blog_id, "user");
+
+-- Likes of blogs that no longer exist can never be shown again.
+DELETE FROM likes WHERE blog_id NOT IN (SELECT id FROM microblogs);
+
+ALTER TABLE likes
+    ADD CONSTRAINT likes_blog_id_fkey FOREIGN KEY (blog_id)
+        REFERENCES microblogs (id) ON DELETE CASCADE;
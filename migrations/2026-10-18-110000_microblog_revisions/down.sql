@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE microblog_revisions;
+
+DROP TRIGGER set_updated_at ON microblogs;
+
+ALTER TABLE microblogs DROP COLUMN updated_at;
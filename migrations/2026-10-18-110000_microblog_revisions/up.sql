@@ -0,0 +1,15 @@
+-- Your SQL goes here
+
+-- NULL until the blog is edited for the first time.
+ALTER TABLE microblogs ADD COLUMN updated_at TIMESTAMP;
+
+SELECT diesel_manage_updated_at('microblogs');
+
+CREATE TABLE microblog_revisions (
+    id UUID PRIMARY KEY,
+    blog_id UUID NOT NULL REFERENCES microblogs (id) ON DELETE CASCADE,
+    blog_message TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX microblog_revisions_blog_id_idx ON microblog_revisions (blog_id, created_at);
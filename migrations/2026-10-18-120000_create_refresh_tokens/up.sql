@@ -0,0 +1,18 @@
+-- Your SQL goes here
+
+-- Every login starts a new token family. Refreshing rotates the token within
+-- its family, and presenting a rotated token again revokes the whole family.
+-- Expired tokens are purged whenever new ones are issued.
+CREATE TABLE refresh_tokens (
+    token_uuid UUID PRIMARY KEY,
+    family_id UUID NOT NULL,
+    user_email VARCHAR(50) NOT NULL REFERENCES users (email) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    rotated_at TIMESTAMP,
+    revoked_at TIMESTAMP
+);
+
+CREATE INDEX refresh_tokens_family_id_idx ON refresh_tokens (family_id);
+CREATE INDEX refresh_tokens_expires_at_idx ON refresh_tokens (expires_at);
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+-- Access tokens revoked before they expire. Rows are only needed until the
+-- token would have expired anyway and are purged after that.
+CREATE TABLE revoked_tokens (
+    token_uuid UUID PRIMARY KEY,
+    user_email VARCHAR(50) NOT NULL,
+    revoked_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX revoked_tokens_expires_at_idx ON revoked_tokens (expires_at);
@@ -0,0 +1,25 @@
+-- This file should undo anything in `up.sql`
+
+-- Lowercased emails are left as they are.
+ALTER TABLE users DROP CONSTRAINT users_email_lowercase;
+
+ALTER TABLE revoked_tokens ALTER COLUMN user_email TYPE VARCHAR(50);
+ALTER TABLE refresh_tokens ALTER COLUMN user_email TYPE VARCHAR(50);
+ALTER TABLE likes ALTER COLUMN "user" TYPE VARCHAR(50);
+ALTER TABLE microblogs ALTER COLUMN author TYPE VARCHAR(50);
+ALTER TABLE users ALTER COLUMN email TYPE VARCHAR(50);
+
+ALTER TABLE refresh_tokens
+    DROP CONSTRAINT refresh_tokens_user_email_fkey,
+    ADD CONSTRAINT refresh_tokens_user_email_fkey FOREIGN KEY (user_email)
+        REFERENCES users (email) ON DELETE CASCADE;
+
+ALTER TABLE likes
+    DROP CONSTRAINT likes_user_fkey,
+    ADD CONSTRAINT likes_user_fkey FOREIGN KEY ("user")
+        REFERENCES users (email) ON DELETE CASCADE;
+
+ALTER TABLE microblogs
+    DROP CONSTRAINT microblogs_author_fkey,
+    ADD CONSTRAINT microblogs_author_fkey FOREIGN KEY (author)
+        REFERENCES users (email) ON DELETE SET NULL;
@@ -0,0 +1,34 @@
+-- Your SQL goes here
+
+-- Emails are the users primary key and are stored lowercased from now on.
+-- References follow when existing mixed-case addresses are lowercased. If two
+-- accounts only differ in case the update fails and has to be resolved by
+-- hand. Columns are widened to the 254 characters RFC 5321 allows.
+ALTER TABLE microblogs
+    DROP CONSTRAINT microblogs_author_fkey,
+    ADD CONSTRAINT microblogs_author_fkey FOREIGN KEY (author)
+        REFERENCES users (email) ON UPDATE CASCADE ON DELETE SET NULL;
+
+ALTER TABLE likes
+    DROP CONSTRAINT likes_user_fkey,
+    ADD CONSTRAINT likes_user_fkey FOREIGN KEY ("user")
+        REFERENCES users (email) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE refresh_tokens
+    DROP CONSTRAINT refresh_tokens_user_email_fkey,
+    ADD CONSTRAINT refresh_tokens_user_email_fkey FOREIGN KEY (user_email)
+        REFERENCES users (email) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE users ALTER COLUMN email TYPE VARCHAR(254);
+ALTER TABLE microblogs ALTER COLUMN author TYPE VARCHAR(254);
+ALTER TABLE likes ALTER COLUMN "user" TYPE VARCHAR(254);
+ALTER TABLE refresh_tokens ALTER COLUMN user_email TYPE VARCHAR(254);
+ALTER TABLE revoked_tokens ALTER COLUMN user_email TYPE VARCHAR(254);
+
+-- The cascade into microblogs.author must not mark every post as edited.
+ALTER TABLE microblogs DISABLE TRIGGER set_updated_at;
+UPDATE users SET email = LOWER(email) WHERE email <> LOWER(email);
+ALTER TABLE microblogs ENABLE TRIGGER set_updated_at;
+UPDATE revoked_tokens SET user_email = LOWER(user_email) WHERE user_email <> LOWER(user_email);
+
+ALTER TABLE users ADD CONSTRAINT users_email_lowercase CHECK (email = LOWER(email));
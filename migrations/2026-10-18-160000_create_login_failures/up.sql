@@ -0,0 +1,16 @@
+-- Your SQL goes here
+
+-- Failed logins, counted per account and per client address. Accounts are
+-- tracked by the email that was tried, whether or not it exists, so lockouts
+-- do not reveal which emails are registered. Rows older than the lockout
+-- window no longer count and are purged.
+CREATE TABLE login_failures (
+    scope VARCHAR(10) NOT NULL,
+    subject VARCHAR(254) NOT NULL,
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_failure_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    locked_until TIMESTAMP,
+    PRIMARY KEY (scope, subject)
+);
+
+CREATE INDEX login_failures_last_failure_at_idx ON login_failures (last_failure_at);
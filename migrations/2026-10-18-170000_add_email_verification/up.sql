@@ -0,0 +1,6 @@
+-- Your SQL goes here
+
+-- Accounts that existed before verification was introduced count as verified.
+ALTER TABLE users ADD COLUMN email_verified_at TIMESTAMP;
+
+UPDATE users SET email_verified_at = NOW();
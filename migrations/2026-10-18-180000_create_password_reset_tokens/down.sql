@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE users DROP COLUMN tokens_invalid_before;
+
+DROP TABLE password_reset_tokens;
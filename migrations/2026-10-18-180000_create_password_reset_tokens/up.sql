@@ -0,0 +1,20 @@
+-- Your SQL goes here
+
+-- Single-use tokens mailed by `POST /password/forgot`. Only a hash of the
+-- token is stored. Expired tokens are purged whenever a new one is requested.
+CREATE TABLE password_reset_tokens (
+    token_uuid UUID PRIMARY KEY,
+    user_email VARCHAR(254) NOT NULL
+        REFERENCES users (email) ON UPDATE CASCADE ON DELETE CASCADE,
+    token_hash TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX password_reset_tokens_user_email_idx ON password_reset_tokens (user_email);
+CREATE INDEX password_reset_tokens_expires_at_idx ON password_reset_tokens (expires_at);
+
+-- Access tokens issued before this moment are rejected. Set when a password
+-- is reset to log the user out everywhere.
+ALTER TABLE users ADD COLUMN tokens_invalid_before TIMESTAMP;
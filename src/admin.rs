@@ -0,0 +1,148 @@
+use actix_web::{
+    delete, get, put,
+    web::{self, Data, Json, Path, Query},
+    HttpResponse,
+};
+use base64::{engine::general_purpose, Engine as _};
+use diesel::{Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use serde::Deserialize;
+
+use crate::authorization::Role;
+use crate::error::AppError;
+use crate::jwtAuth::JWTAuthToken;
+use crate::lockout::{clear_login_failures, LoginScope};
+use crate::response::{page_size, PageQuery, Response};
+use crate::revocation::revoke_all_user_tokens;
+use crate::user::{PublicUser, UserDB};
+use crate::validation::normalize_email;
+use crate::{DBPool, DBPooledConnection};
+
+#[derive(Debug, Deserialize)]
+pub struct RoleRequest {
+    pub role: Role,
+}
+
+/// Users are listed by email, so the cursor is just the last email seen.
+fn decode_email_cursor(cursor: Option<&str>) -> Result<Option<String>, AppError> {
+    cursor
+        .map(|cursor| {
+            general_purpose::URL_SAFE_NO_PAD
+                .decode(cursor)
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or_else(|| AppError::Validation("Invalid cursor".to_string()))
+        })
+        .transpose()
+}
+
+fn list_users(
+    total: i64,
+    after: Option<String>,
+    conn: &mut DBPooledConnection,
+) -> Result<Response<PublicUser>, AppError> {
+    use crate::schema::users::dsl::*;
+
+    let mut query = users.order(email.asc()).limit(total + 1).into_boxed();
+    if let Some(after) = after {
+        query = query.filter(email.gt(after));
+    }
+
+    let mut page = query.load::<UserDB>(conn)?;
+
+    let next_cursor = if page.len() as i64 > total {
+        page.truncate(total as usize);
+        page.last()
+            .map(|user| general_purpose::URL_SAFE_NO_PAD.encode(&user.email))
+    } else {
+        None
+    };
+
+    Ok(Response {
+        results: page.iter().map(UserDB::to_public_user).collect(),
+        next_cursor,
+    })
+}
+
+/// Changes the role of `_email` and logs the user out everywhere, so that
+/// tokens carrying the old role stop working right away.
+fn set_user_role(
+    _email: &str,
+    new_role: Role,
+    conn: &mut DBPooledConnection,
+) -> Result<UserDB, AppError> {
+    use crate::schema::users::dsl::*;
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        let user = diesel::update(users.find(_email))
+            .set(role.eq(new_role.as_str()))
+            .get_result::<UserDB>(conn)
+            .optional()?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        revoke_all_user_tokens(_email, conn)?;
+
+        Ok(user)
+    })
+}
+
+#[get("/admin/users")]
+async fn users(
+    auth: JWTAuthToken,
+    query: Query<PageQuery>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    auth.require_role(Role::Admin)?;
+
+    let query = query.into_inner();
+    let limit = page_size(query.limit);
+    let after = decode_email_cursor(query.cursor.as_deref())?;
+
+    let mut conn = pool.get()?;
+    let page = web::block(move || list_users(limit, after, &mut conn)).await??;
+
+    Ok(HttpResponse::Ok().json(page))
+}
+
+/// Changes a user's role. The user has to log in again to pick it up.
+#[put("/admin/users/{email}/role")]
+async fn update_role(
+    auth: JWTAuthToken,
+    path: Path<(String,)>,
+    data: Json<RoleRequest>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    auth.require_role(Role::Admin)?;
+
+    let (email,) = path.into_inner();
+    let email =
+        normalize_email(&email).ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    if email == auth.user {
+        return Err(AppError::Forbidden(
+            "You cannot change your own role".to_string(),
+        ));
+    }
+
+    let mut conn = pool.get()?;
+    let user = web::block(move || set_user_role(&email, data.role, &mut conn)).await??;
+
+    Ok(HttpResponse::Ok().json(user.to_public_user()))
+}
+
+/// Lifts a lockout on an account after too many failed logins.
+#[delete("/admin/users/{email}/lockout")]
+async fn unlock(
+    auth: JWTAuthToken,
+    path: Path<(String,)>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    auth.require_role(Role::Admin)?;
+
+    let (email,) = path.into_inner();
+    let email =
+        normalize_email(&email).ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    let mut conn = pool.get()?;
+    web::block(move || clear_login_failures(LoginScope::Account, &email, &mut conn)).await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
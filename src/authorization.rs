@@ -0,0 +1,78 @@
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::jwtAuth::JWTAuthToken;
+use crate::DBPooledConnection;
+
+/// Roles are ordered: every role can do what the roles below it can.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = AppError;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "user" => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(AppError::Validation(format!("Unknown role `{}`", role))),
+        }
+    }
+}
+
+impl JWTAuthToken {
+    /// Guard for handlers that need at least `role`.
+    pub fn require_role(&self, role: Role) -> Result<(), AppError> {
+        if self.role >= role {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(
+                "You do not have permission to perform this action".to_string(),
+            ))
+        }
+    }
+}
+
+/// Checks that `user` is the author of the blog before a mutating request
+/// is allowed to touch it. Blogs without an author belong to nobody.
+pub fn authorize_blog_owner(
+    blog_id: Uuid,
+    user: &str,
+    conn: &mut DBPooledConnection,
+) -> Result<(), AppError> {
+    use crate::schema::microblogs::dsl::*;
+
+    let blog_author = microblogs
+        .filter(id.eq(blog_id))
+        .select(author)
+        .first::<Option<String>>(conn)
+        .optional()?;
+
+    match blog_author {
+        None => Err(AppError::NotFound("Blog not found".to_string())),
+        Some(Some(owner)) if owner == user => Ok(()),
+        Some(_) => Err(AppError::Forbidden(
+            "You are not allowed to modify this blog".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,345 @@
+use base64::{engine::general_purpose, Engine as _};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use std::env;
+use std::path::PathBuf;
+
+use crate::lockout::LoginThrottle;
+use crate::mail::MailTransport;
+use crate::token::KeyStore;
+use crate::validation::{CommonPasswords, PasswordPolicy, BCRYPT_MAX_PASSWORD_BYTES};
+
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:8000";
+const DEFAULT_POOL_SIZE: u32 = 10;
+const DEFAULT_LOG_LEVEL: &str = "actix_web=debug,actix_server=info";
+const DEFAULT_KEY_ID: &str = "default";
+const DEFAULT_TOKEN_ISSUER: &str = "micro-blogging";
+const DEFAULT_TOKEN_AUDIENCE: &str = "micro-blogging";
+const DEFAULT_TOKEN_LEEWAY: u64 = 60;
+const DEFAULT_PASSWORD_MIN_LENGTH: usize = 8;
+const DEFAULT_LOGIN_MAX_ACCOUNT_FAILURES: i32 = 5;
+const DEFAULT_LOGIN_MAX_IP_FAILURES: i32 = 20;
+const DEFAULT_LOGIN_LOCKOUT_MINUTES: i64 = 15;
+const DEFAULT_MAIL_FROM: &str = "Micro Blogging <no-reply@localhost>";
+const DEFAULT_EMAIL_VERIFICATION_MAX_AGE: i64 = 24 * 60;
+const DEFAULT_PASSWORD_RESET_MAX_AGE: i64 = 60;
+/// HMAC secrets must be at least as long as the SHA-256 output.
+const MIN_SECRET_LENGTH: usize = 32;
+
+/// Settings read from the environment once at startup and shared with the
+/// handlers through `web::Data<Config>`.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub database_url: String,
+    pub bind_address: String,
+    pub pool_size: u32,
+    pub log_level: String,
+    /// Keys used to sign and verify access tokens.
+    pub access_token_keys: KeyStore,
+    /// Access token lifetime in minutes.
+    pub access_token_max_age: i64,
+    /// Refresh token lifetime in minutes.
+    pub refresh_token_max_age: i64,
+    /// `iss` claim put into and required from access tokens.
+    pub token_issuer: String,
+    /// `aud` claim put into and required from access tokens.
+    pub token_audience: String,
+    /// Allowed clock skew in seconds when checking `exp`, `nbf` and `iat`.
+    pub token_leeway: u64,
+    pub password_policy: PasswordPolicy,
+    pub login_throttle: LoginThrottle,
+    /// Take the client address from the `Forwarded` or `X-Forwarded-For`
+    /// header instead of the connection. Only safe behind a reverse proxy
+    /// that overwrites those headers.
+    pub trust_proxy_headers: bool,
+    pub mail_transport: MailTransport,
+    pub mail_from: String,
+    /// Base URL of this service, used for links in emails.
+    pub public_url: String,
+    /// Lifetime of email verification links in minutes.
+    pub email_verification_max_age: i64,
+    /// Lifetime of password reset tokens in minutes.
+    pub password_reset_max_age: i64,
+}
+
+impl Config {
+    /// Reads and validates the configuration. Returns a message naming the
+    /// offending variable if anything is missing or malformed.
+    pub fn from_env() -> Result<Config, String> {
+        let bind_address =
+            env::var("BIND_ADDRESS").unwrap_or_else(|_| DEFAULT_BIND_ADDRESS.to_string());
+
+        Ok(Config {
+            database_url: required_var("DATABASE_URL")?,
+            public_url: env::var("PUBLIC_URL")
+                .unwrap_or_else(|_| format!("http://{}", bind_address))
+                .trim_end_matches('/')
+                .to_string(),
+            bind_address,
+            pool_size: match env::var("DATABASE_POOL_SIZE") {
+                Ok(size) => size
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|size| *size > 0)
+                    .ok_or("DATABASE_POOL_SIZE must be a positive number")?,
+                Err(_) => DEFAULT_POOL_SIZE,
+            },
+            log_level: env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_LOG_LEVEL.to_string()),
+            access_token_keys: key_store()?,
+            access_token_max_age: minutes_var("ACCESS_TOKEN_MAX_AGE")?,
+            refresh_token_max_age: minutes_var("REFRESH_TOKEN_MAX_AGE")?,
+            token_issuer: env::var("ACCESS_TOKEN_ISSUER")
+                .unwrap_or_else(|_| DEFAULT_TOKEN_ISSUER.to_string()),
+            token_audience: env::var("ACCESS_TOKEN_AUDIENCE")
+                .unwrap_or_else(|_| DEFAULT_TOKEN_AUDIENCE.to_string()),
+            token_leeway: match env::var("ACCESS_TOKEN_LEEWAY") {
+                Ok(leeway) => leeway
+                    .parse::<u64>()
+                    .map_err(|_| "ACCESS_TOKEN_LEEWAY must be a number of seconds")?,
+                Err(_) => DEFAULT_TOKEN_LEEWAY,
+            },
+            password_policy: password_policy()?,
+            login_throttle: login_throttle()?,
+            trust_proxy_headers: bool_var("TRUST_PROXY_HEADERS")?,
+            mail_transport: match env::var("MAILER").as_deref() {
+                Err(_) | Ok("stdout") => MailTransport::Stdout,
+                Ok("file") => MailTransport::File(PathBuf::from(required_var("MAIL_DIR")?)),
+                Ok("smtp") => MailTransport::Smtp(required_var("SMTP_URL")?),
+                Ok(other) => {
+                    return Err(format!(
+                        "MAILER `{}` is not one of smtp, file or stdout",
+                        other
+                    ))
+                }
+            },
+            mail_from: env::var("MAIL_FROM").unwrap_or_else(|_| DEFAULT_MAIL_FROM.to_string()),
+            email_verification_max_age: positive_var(
+                "EMAIL_VERIFICATION_MAX_AGE",
+                DEFAULT_EMAIL_VERIFICATION_MAX_AGE,
+            )?,
+            password_reset_max_age: positive_var(
+                "PASSWORD_RESET_MAX_AGE",
+                DEFAULT_PASSWORD_RESET_MAX_AGE,
+            )?,
+        })
+    }
+}
+
+/// The current key is read under the id `ACCESS_TOKEN_KEY_ID`. For RS256 and
+/// EdDSA that is the PEM pair in `BASE64_ACCESS_TOKEN_PRIVATE_KEY` and
+/// `BASE64_ACCESS_TOKEN_PUBLIC_KEY`; for HS256 it is the shared secret in
+/// `BASE64_ACCESS_TOKEN_SECRET`. Retired keys go in
+/// `ACCESS_TOKEN_VERIFICATION_KEYS` as a comma separated list of
+/// `<kid>:<base64 public key or secret>` entries.
+fn key_store() -> Result<KeyStore, String> {
+    let algorithm = match env::var("ACCESS_TOKEN_ALGORITHM").as_deref() {
+        Err(_) | Ok("RS256") => Algorithm::RS256,
+        Ok("EdDSA") => Algorithm::EdDSA,
+        Ok("HS256") => Algorithm::HS256,
+        Ok(other) => {
+            return Err(format!(
+                "ACCESS_TOKEN_ALGORITHM `{}` is not one of RS256, EdDSA or HS256",
+                other
+            ))
+        }
+    };
+    let kid = env::var("ACCESS_TOKEN_KEY_ID").unwrap_or_else(|_| DEFAULT_KEY_ID.to_string());
+
+    let (encoding_key, decoding_key) = match algorithm {
+        Algorithm::HS256 => {
+            let secret = required_var("BASE64_ACCESS_TOKEN_SECRET")?;
+            (
+                parse_encoding_key(algorithm, "BASE64_ACCESS_TOKEN_SECRET", &secret)?,
+                parse_decoding_key(algorithm, "BASE64_ACCESS_TOKEN_SECRET", &secret)?,
+            )
+        }
+        _ => (
+            parse_encoding_key(
+                algorithm,
+                "BASE64_ACCESS_TOKEN_PRIVATE_KEY",
+                &required_var("BASE64_ACCESS_TOKEN_PRIVATE_KEY")?,
+            )?,
+            parse_decoding_key(
+                algorithm,
+                "BASE64_ACCESS_TOKEN_PUBLIC_KEY",
+                &required_var("BASE64_ACCESS_TOKEN_PUBLIC_KEY")?,
+            )?,
+        ),
+    };
+
+    let mut keys = KeyStore::new(algorithm, kid, encoding_key, decoding_key);
+
+    let verification_keys = env::var("ACCESS_TOKEN_VERIFICATION_KEYS").unwrap_or_default();
+    for entry in verification_keys
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+    {
+        let (kid, key) = entry.trim().split_once(':').ok_or_else(|| {
+            format!(
+                "ACCESS_TOKEN_VERIFICATION_KEYS entry `{}` is not of the form <kid>:<key>",
+                entry
+            )
+        })?;
+        let decoding_key = parse_decoding_key(
+            algorithm,
+            &format!("ACCESS_TOKEN_VERIFICATION_KEYS key `{}`", kid),
+            key,
+        )?;
+        keys.add_verification_key(kid.to_string(), decoding_key);
+    }
+
+    Ok(keys)
+}
+
+fn parse_encoding_key(
+    algorithm: Algorithm,
+    name: &str,
+    value: &str,
+) -> Result<EncodingKey, String> {
+    match algorithm {
+        Algorithm::HS256 => Ok(EncodingKey::from_secret(&decode_secret(name, value)?)),
+        Algorithm::EdDSA => EncodingKey::from_ed_pem(decode_pem(name, value)?.as_bytes())
+            .map_err(|err| format!("{} is not an Ed25519 private key: {}", name, err)),
+        _ => EncodingKey::from_rsa_pem(decode_pem(name, value)?.as_bytes())
+            .map_err(|err| format!("{} is not an RSA private key: {}", name, err)),
+    }
+}
+
+fn parse_decoding_key(
+    algorithm: Algorithm,
+    name: &str,
+    value: &str,
+) -> Result<DecodingKey, String> {
+    match algorithm {
+        Algorithm::HS256 => Ok(DecodingKey::from_secret(&decode_secret(name, value)?)),
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(decode_pem(name, value)?.as_bytes())
+            .map_err(|err| format!("{} is not an Ed25519 public key: {}", name, err)),
+        _ => DecodingKey::from_rsa_pem(decode_pem(name, value)?.as_bytes())
+            .map_err(|err| format!("{} is not an RSA public key: {}", name, err)),
+    }
+}
+
+/// Lengths come from `PASSWORD_MIN_LENGTH` and `PASSWORD_MAX_LENGTH`, the
+/// latter capped at what bcrypt can hash. Character classes are switched on
+/// with `PASSWORD_REQUIRE_{LOWERCASE,UPPERCASE,DIGIT,SYMBOL}=true`.
+/// `COMMON_PASSWORDS_FILE` names a list of passwords to reject on top of the
+/// bundled one.
+fn password_policy() -> Result<PasswordPolicy, String> {
+    let min_length = match env::var("PASSWORD_MIN_LENGTH") {
+        Ok(length) => length
+            .parse::<usize>()
+            .ok()
+            .filter(|length| *length > 0)
+            .ok_or("PASSWORD_MIN_LENGTH must be a positive number")?,
+        Err(_) => DEFAULT_PASSWORD_MIN_LENGTH,
+    };
+
+    let max_length = match env::var("PASSWORD_MAX_LENGTH") {
+        Ok(length) => length
+            .parse::<usize>()
+            .ok()
+            .filter(|length| (min_length..=BCRYPT_MAX_PASSWORD_BYTES).contains(length))
+            .ok_or_else(|| {
+                format!(
+                    "PASSWORD_MAX_LENGTH must be between PASSWORD_MIN_LENGTH and {}",
+                    BCRYPT_MAX_PASSWORD_BYTES
+                )
+            })?,
+        Err(_) => BCRYPT_MAX_PASSWORD_BYTES,
+    };
+
+    if min_length > max_length {
+        return Err(format!(
+            "PASSWORD_MIN_LENGTH must not be more than {}",
+            max_length
+        ));
+    }
+
+    Ok(PasswordPolicy {
+        min_length,
+        max_length,
+        require_lowercase: bool_var("PASSWORD_REQUIRE_LOWERCASE")?,
+        require_uppercase: bool_var("PASSWORD_REQUIRE_UPPERCASE")?,
+        require_digit: bool_var("PASSWORD_REQUIRE_DIGIT")?,
+        require_symbol: bool_var("PASSWORD_REQUIRE_SYMBOL")?,
+        common_passwords: match env::var("COMMON_PASSWORDS_FILE") {
+            Ok(path) => CommonPasswords::with_file(&path)
+                .map_err(|err| format!("COMMON_PASSWORDS_FILE cannot be read: {}", err))?,
+            Err(_) => CommonPasswords::bundled(),
+        },
+    })
+}
+
+/// Failed login limits from `LOGIN_MAX_ACCOUNT_FAILURES`,
+/// `LOGIN_MAX_IP_FAILURES` and `LOGIN_LOCKOUT_MINUTES`.
+fn login_throttle() -> Result<LoginThrottle, String> {
+    let throttle = LoginThrottle {
+        max_account_failures: positive_var(
+            "LOGIN_MAX_ACCOUNT_FAILURES",
+            DEFAULT_LOGIN_MAX_ACCOUNT_FAILURES,
+        )?,
+        max_ip_failures: positive_var("LOGIN_MAX_IP_FAILURES", DEFAULT_LOGIN_MAX_IP_FAILURES)?,
+        lockout_minutes: positive_var("LOGIN_LOCKOUT_MINUTES", DEFAULT_LOGIN_LOCKOUT_MINUTES)?,
+    };
+    throttle.validate()?;
+
+    Ok(throttle)
+}
+
+fn positive_var<T>(name: &str, default: T) -> Result<T, String>
+where
+    T: std::str::FromStr + PartialOrd + Default,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse::<T>()
+            .ok()
+            .filter(|value| *value > T::default())
+            .ok_or_else(|| format!("{} must be a positive number", name)),
+        Err(_) => Ok(default),
+    }
+}
+
+fn bool_var(name: &str) -> Result<bool, String> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse::<bool>()
+            .map_err(|_| format!("{} must be true or false", name)),
+        Err(_) => Ok(false),
+    }
+}
+
+fn required_var(name: &str) -> Result<String, String> {
+    env::var(name).map_err(|_| format!("{} is not set", name))
+}
+
+fn minutes_var(name: &str) -> Result<i64, String> {
+    required_var(name)?
+        .parse::<i64>()
+        .ok()
+        .filter(|minutes| *minutes > 0)
+        .ok_or_else(|| format!("{} must be a positive number of minutes", name))
+}
+
+/// Keys are stored base64 encoded so they fit on a single line in `.env`.
+fn decode_base64(name: &str, value: &str) -> Result<Vec<u8>, String> {
+    general_purpose::STANDARD
+        .decode(value)
+        .map_err(|err| format!("{} is not valid base64: {}", name, err))
+}
+
+fn decode_pem(name: &str, value: &str) -> Result<String, String> {
+    String::from_utf8(decode_base64(name, value)?)
+        .map_err(|_| format!("{} does not decode to a PEM string", name))
+}
+
+fn decode_secret(name: &str, value: &str) -> Result<Vec<u8>, String> {
+    let secret = decode_base64(name, value)?;
+    if secret.len() < MIN_SECRET_LENGTH {
+        return Err(format!(
+            "{} must be at least {} bytes long",
+            name, MIN_SECRET_LENGTH
+        ));
+    }
+
+    Ok(secret)
+}
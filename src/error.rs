@@ -0,0 +1,127 @@
+use actix_web::error::BlockingError;
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use core::fmt;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+use crate::jwtAuth::ErrorResponse;
+
+/// Every way a request can fail. Handlers return `Result<HttpResponse, AppError>`
+/// and actix renders the error as an `ErrorResponse` with a matching status code.
+#[derive(Debug)]
+pub enum AppError {
+    Validation(String),
+    /// A validation failure with every individual problem listed in `errors`.
+    ValidationErrors(String, Vec<String>),
+    NotFound(String),
+    Conflict(String),
+    Unauthorized(String),
+    Forbidden(String),
+    /// Rejected by rate limiting; the second field is the number of seconds
+    /// to wait before trying again.
+    TooManyRequests(String, u64),
+    Database(DieselError),
+    PoolExhausted(r2d2::Error),
+    Internal(String),
+}
+
+impl AppError {
+    pub fn message(&self) -> String {
+        match self {
+            AppError::Validation(message)
+            | AppError::ValidationErrors(message, _)
+            | AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::TooManyRequests(message, _) => message.to_string(),
+            AppError::Database(_) => "Error while accessing the database".to_string(),
+            AppError::PoolExhausted(_) => "Service is busy, please try again later".to_string(),
+            AppError::Internal(_) => "Internal server error".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Database(err) => write!(f, "Database error: {}", err),
+            AppError::PoolExhausted(err) => write!(f, "Connection pool error: {}", err),
+            AppError::Internal(err) => write!(f, "Internal error: {}", err),
+            _ => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) | AppError::ValidationErrors(..) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Database(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::PoolExhausted(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if self.status_code().is_server_error() {
+            println!("{}", self);
+        }
+
+        let mut response = HttpResponse::build(self.status_code());
+        if let AppError::TooManyRequests(_, retry_after) = self {
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+
+        response.json(ErrorResponse {
+            status: "FAILED".to_string(),
+            message: self.message(),
+            errors: match self {
+                AppError::ValidationErrors(_, errors) => errors.clone(),
+                _ => Vec::new(),
+            },
+        })
+    }
+}
+
+impl From<DieselError> for AppError {
+    fn from(err: DieselError) -> Self {
+        match err {
+            DieselError::NotFound => AppError::NotFound("Resource not found".to_string()),
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                AppError::Conflict("Resource already exists".to_string())
+            }
+            err => AppError::Database(err),
+        }
+    }
+}
+
+impl From<r2d2::Error> for AppError {
+    fn from(err: r2d2::Error) -> Self {
+        AppError::PoolExhausted(err)
+    }
+}
+
+impl From<BlockingError> for AppError {
+    fn from(err: BlockingError) -> Self {
+        AppError::Internal(err.to_string())
+    }
+}
+
+impl From<bcrypt::BcryptError> for AppError {
+    fn from(err: bcrypt::BcryptError) -> Self {
+        AppError::Internal(err.to_string())
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for AppError {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        AppError::Internal(err.to_string())
+    }
+}
@@ -1,23 +1,37 @@
+use actix_web::cookie::{time::Duration, Cookie, SameSite};
 use actix_web::dev::Payload;
-use actix_web::error::ErrorUnauthorized;
-use actix_web::{http, Error as ActixWebError, FromRequest, HttpRequest};
+use actix_web::web::{self, Data};
+use actix_web::{http, FromRequest, HttpRequest};
+use base64::{engine::general_purpose, Engine as _};
 use core::fmt;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
-use std::env;
-use std::future::{ready, Ready};
+use std::future::Future;
+use std::pin::Pin;
+use uuid::Uuid;
 
+use crate::authorization::Role;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::revocation::is_token_revoked;
 use crate::token;
+use crate::DBPool;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JWTAuthToken {
-    user: String,
-    access_token: String,
+    pub user: String,
+    pub access_token: String,
+    pub token_uuid: Uuid,
+    pub expires_in: i64,
+    pub role: Role,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     pub status: String,
     pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
 }
 
 impl fmt::Display for ErrorResponse {
@@ -26,52 +40,138 @@ impl fmt::Display for ErrorResponse {
     }
 }
 
-impl FromRequest for JWTAuthToken {
-    type Error = ActixWebError;
-    type Future = Ready<Result<Self, Self::Error>>;
+pub const ACCESS_TOKEN_COOKIE: &str = "access_token";
+pub const CSRF_TOKEN_COOKIE: &str = "csrf_token";
+pub const CSRF_TOKEN_HEADER: &str = "X-CSRF-Token";
+
+/// Builds the cookies set on login: the access token itself, which scripts
+/// cannot read, and a CSRF token that the client must echo back in the
+/// `X-CSRF-Token` header on mutating requests authenticated by the cookie.
+pub fn auth_cookies(access_token: &str, ttl: i64) -> (Cookie<'static>, Cookie<'static>) {
+    let mut csrf_token = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut csrf_token);
+
+    let access_cookie = Cookie::build(ACCESS_TOKEN_COOKIE, access_token.to_string())
+        .path("/")
+        .secure(true)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(Duration::minutes(ttl))
+        .finish();
+
+    let csrf_cookie = Cookie::build(
+        CSRF_TOKEN_COOKIE,
+        general_purpose::URL_SAFE_NO_PAD.encode(csrf_token),
+    )
+    .path("/")
+    .secure(true)
+    .http_only(false)
+    .same_site(SameSite::Lax)
+    .max_age(Duration::minutes(ttl))
+    .finish();
+
+    (access_cookie, csrf_cookie)
+}
+
+/// Expired copies of the auth cookies, used to clear them on logout.
+pub fn clear_auth_cookies() -> (Cookie<'static>, Cookie<'static>) {
+    let (mut access_cookie, mut csrf_cookie) = auth_cookies("", 0);
+    access_cookie.make_removal();
+    csrf_cookie.make_removal();
+
+    (access_cookie, csrf_cookie)
+}
+
+/// Double-submit check: the `X-CSRF-Token` header must match the
+/// `csrf_token` cookie. Compared in constant time.
+fn csrf_token_matches(req: &HttpRequest) -> bool {
+    let cookie = req.cookie(CSRF_TOKEN_COOKIE);
+    let header = req
+        .headers()
+        .get(CSRF_TOKEN_HEADER)
+        .and_then(|header| header.to_str().ok());
+
+    match (cookie, header) {
+        (Some(cookie), Some(header)) => {
+            let (cookie, header) = (cookie.value().as_bytes(), header.as_bytes());
+            !cookie.is_empty()
+                && cookie.len() == header.len()
+                && cookie
+                    .iter()
+                    .zip(header)
+                    .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        }
+        _ => false,
+    }
+}
 
-    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
-        let access_token_public_key =
-            env::var("BASE64_ACCESS_TOKEN_PUBLIC_KEY").expect("Failed to fetch access token ");
+impl FromRequest for JWTAuthToken {
+    type Error = AppError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
 
-        let access_token = req
-            .cookie("access_token")
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let cookie_token = req
+            .cookie(ACCESS_TOKEN_COOKIE)
             .map(|cookie| cookie.value().to_string())
-            .or_else(|| {
-                req.headers()
-                    .get(http::header::AUTHORIZATION)
-                    .map(|header| header.to_str().unwrap().split_at(7).1.to_string())
-                // split at 7 is to separate Bearer keyword and token.
-            });
-
-        println!("{:?}", access_token);
-
-        if access_token.is_none() {
-            let json_error = ErrorResponse {
-                status: "FAILED".to_string(),
-                message: "You are not logged in, please provide token".to_string(),
-            };
-            return ready(Err(ErrorUnauthorized(json_error)));
-        };
-
-        let access_token_details =
-            match token::verify_jwt_token(&access_token.unwrap(), access_token_public_key) {
-                Ok(res) => res,
-                Err(err) => {
-                    let json_error = ErrorResponse {
-                        status: "FAILED".to_string(),
-                        message: format!("{:?}", err),
-                    };
-                    return ready(Err(ErrorUnauthorized(json_error)));
-                }
-            };
-
-        let user = access_token_details.user;
-        let access_jwt_token = access_token_details.access_token.unwrap();
-
-        ready(Ok(JWTAuthToken {
-            user: user,
-            access_token: access_jwt_token,
-        }))
+            .filter(|token| !token.is_empty());
+
+        // Browsers attach cookies to cross-site requests on their own, so a
+        // cookie-authenticated request that changes state must prove it came
+        // from our client. Bearer tokens are never sent implicitly.
+        let csrf_failed =
+            cookie_token.is_some() && !req.method().is_safe() && !csrf_token_matches(req);
+
+        let access_token = cookie_token.or_else(|| {
+            req.headers()
+                .get(http::header::AUTHORIZATION)
+                .and_then(|header| header.to_str().ok())
+                .and_then(|header| header.strip_prefix("Bearer "))
+                .map(|token| token.to_string())
+        });
+
+        let config = req.app_data::<Data<Config>>().cloned();
+        let pool = req.app_data::<Data<DBPool>>().cloned();
+
+        Box::pin(async move {
+            if csrf_failed {
+                return Err(AppError::Forbidden(
+                    "Missing or invalid CSRF token".to_string(),
+                ));
+            }
+
+            let access_token = access_token.ok_or_else(|| {
+                AppError::Unauthorized("You are not logged in, please provide token".to_string())
+            })?;
+
+            let config = config
+                .ok_or_else(|| AppError::Internal("Configuration is not loaded".to_string()))?;
+
+            let access_token_details = token::verify_jwt_token(&access_token, &config)
+                .map_err(|err| AppError::Unauthorized(format!("{:?}", err)))?;
+
+            let pool = pool
+                .ok_or_else(|| AppError::Internal("Database pool is not configured".to_string()))?;
+            let mut conn = pool.get()?;
+
+            let token_uuid = access_token_details.token_uuid;
+            let user = access_token_details.user.clone();
+            let issued_at_us = access_token_details.issued_at_us;
+            if web::block(move || is_token_revoked(token_uuid, &user, issued_at_us, &mut conn))
+                .await??
+            {
+                return Err(AppError::Unauthorized(
+                    "Token has been revoked, please log in again".to_string(),
+                ));
+            }
+
+            Ok(JWTAuthToken {
+                user: access_token_details.user,
+                access_token,
+                token_uuid,
+                expires_in: access_token_details.expires_in.unwrap_or_default(),
+                role: access_token_details.role,
+            })
+        })
     }
 }
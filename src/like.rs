@@ -1,17 +1,22 @@
-use actix_web::web::{self, Data, Path};
+use actix_web::web::{self, Data, Path, Query};
 use actix_web::{delete, get, post, HttpResponse};
 use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use diesel::prelude::*;
-use diesel::result::Error;
+use diesel::result::{DatabaseErrorKind, Error};
 // use diesel::serialize::Result;
 use diesel::{ExpressionMethods, Insertable, Queryable, RunQueryDsl};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 use uuid::Uuid;
 
 use super::schema::likes;
+use crate::error::AppError;
+use crate::jwtAuth::JWTAuthToken;
+use crate::microblog::parse_blog_id;
+use crate::response::{page_size, parse_cursor, Cursor, PageQuery, Response};
 use crate::DBPool;
-use crate::{response::Response, DBPooledConnection};
+use crate::DBPooledConnection;
 
 pub type Likes = Response<Like>;
 
@@ -19,33 +24,36 @@ pub type Likes = Response<Like>;
 pub struct Like {
     pub id: String,
     pub created_at: DateTime<Utc>,
+    pub user: Option<String>,
 }
 
 impl Like {
-    pub fn new() -> Self {
+    pub fn new(user: &str) -> Self {
         Self {
             id: Uuid::new_v4().to_string(),
             created_at: Utc::now(),
+            user: Some(user.to_string()),
         }
     }
 
     pub fn to_db_likes(&self, blog_id: Uuid) -> LikeDB {
         let id = Uuid::from_str(self.id.as_ref()).unwrap();
         LikeDB {
-            id: id,
+            id,
             created_at: Utc::now().naive_utc(),
-            blog_id: blog_id,
+            blog_id,
+            user: self.user.clone(),
         }
     }
 }
 
-#[diesel(table_name = likes)]
 #[derive(Queryable, Insertable)]
-
+#[diesel(table_name = likes)]
 pub struct LikeDB {
     pub id: Uuid,
     pub created_at: NaiveDateTime,
     pub blog_id: Uuid,
+    pub user: Option<String>,
 }
 
 impl LikeDB {
@@ -53,20 +61,47 @@ impl LikeDB {
         Like {
             id: self.id.to_string(),
             created_at: Utc.from_utc_datetime(&self.created_at),
+            user: self.user.clone(),
         }
     }
 }
 
-pub fn like_lists(_blog_id: Uuid, conn: &mut DBPooledConnection) -> Result<Likes, Error> {
+/// Lists the likes of a blog newest first, one page at a time.
+pub fn like_lists(
+    _blog_id: Uuid,
+    total_likes: i64,
+    cursor: Option<Cursor>,
+    conn: &mut DBPooledConnection,
+) -> Result<Likes, Error> {
     use crate::schema::likes::dsl::*;
 
-    let _likes: Vec<LikeDB> = match likes
+    let mut query = likes
         .filter(blog_id.eq(_blog_id))
-        .order(created_at.desc())
-        .load::<LikeDB>(conn)
-    {
-        Ok(res) => res,
-        Err(_) => vec![],
+        .order((created_at.desc(), id.desc()))
+        .limit(total_likes + 1)
+        .into_boxed();
+
+    if let Some(cursor) = cursor {
+        query = query.filter(
+            created_at
+                .lt(cursor.created_at)
+                .or(created_at.eq(cursor.created_at).and(id.lt(cursor.id))),
+        );
+    }
+
+    let mut _likes: Vec<LikeDB> = query.load::<LikeDB>(conn)?;
+
+    let next_cursor = if _likes.len() as i64 > total_likes {
+        _likes.truncate(total_likes as usize);
+        _likes.last().map(|last| {
+            Cursor {
+                created_at: last.created_at,
+                id: last.id,
+            }
+            .encode()
+        })
+    } else {
+        None
     };
 
     Ok(Likes {
@@ -74,87 +109,254 @@ pub fn like_lists(_blog_id: Uuid, conn: &mut DBPooledConnection) -> Result<Likes
             .into_iter()
             .map(|l| l.to_like())
             .collect::<Vec<Like>>(),
+        next_cursor,
     })
 }
 
-fn add_like(_blog_id: Uuid, conn: &mut DBPooledConnection) -> Result<Like, Error> {
+/// Counts the likes of every blog in `blog_ids` with a single grouped query.
+pub fn like_counts_for_blogs(
+    blog_ids: &[Uuid],
+    conn: &mut DBPooledConnection,
+) -> Result<HashMap<Uuid, i64>, Error> {
     use crate::schema::likes::dsl::*;
 
-    let like = Like::new();
-
-    let _ = diesel::insert_into(likes)
-        .values(like.to_db_likes(_blog_id))
-        .execute(conn);
+    let counts = likes
+        .filter(blog_id.eq_any(blog_ids))
+        .group_by(blog_id)
+        .select((blog_id, diesel::dsl::count_star()))
+        .load::<(Uuid, i64)>(conn)?;
 
-    Ok(like)
+    Ok(counts.into_iter().collect())
 }
 
-fn remove_like(_blog_id: Uuid, conn: &mut DBPooledConnection) -> Result<(), Error> {
+/// Returns which of `blog_ids` have been liked by `_user`.
+pub fn liked_blogs(
+    blog_ids: &[Uuid],
+    _user: &str,
+    conn: &mut DBPooledConnection,
+) -> Result<HashSet<Uuid>, Error> {
     use crate::schema::likes::dsl::*;
 
-    let _likes = like_lists(_blog_id, conn);
+    let liked = likes
+        .filter(blog_id.eq_any(blog_ids))
+        .filter(user.eq(_user))
+        .select(blog_id)
+        .load::<Uuid>(conn)?;
 
-    let like = match &_likes {
-        Ok(_likes) if !_likes.results.is_empty() => _likes.results.first(),
-        _ => None,
-    };
+    Ok(liked.into_iter().collect())
+}
+
+/// Loads the likes of every blog in `blog_ids` with a single query, grouped
+/// by blog id.
+pub fn like_lists_for_blogs(
+    blog_ids: &[Uuid],
+    conn: &mut DBPooledConnection,
+) -> Result<HashMap<Uuid, Vec<Like>>, Error> {
+    use crate::schema::likes::dsl::*;
+
+    let _likes: Vec<LikeDB> = likes
+        .filter(blog_id.eq_any(blog_ids))
+        .order(created_at.desc())
+        .load::<LikeDB>(conn)?;
 
-    if like.is_none() {
-        return Ok(());
+    let mut grouped: HashMap<Uuid, Vec<Like>> = HashMap::new();
+    for like in _likes {
+        grouped
+            .entry(like.blog_id)
+            .or_default()
+            .push(like.to_like());
     }
 
-    let like_id = Uuid::from_str(like.unwrap().id.as_str()).unwrap();
+    Ok(grouped)
+}
 
-    let res = diesel::delete(likes.filter(id.eq(like_id))).execute(conn);
+fn add_like(
+    _blog_id: Uuid,
+    _user: &str,
+    conn: &mut DBPooledConnection,
+) -> Result<(Like, bool), Error> {
+    use crate::schema::likes::dsl::*;
+
+    let like = Like::new(_user);
 
-    match res {
-        Ok(_) => Ok(()),
-        Err(err) => Err(err),
+    let inserted = diesel::insert_into(likes)
+        .values(like.to_db_likes(_blog_id))
+        .on_conflict((blog_id, user))
+        .do_nothing()
+        .execute(conn)?;
+
+    if inserted > 0 {
+        return Ok((like, true));
     }
+
+    let existing = likes
+        .filter(blog_id.eq(_blog_id))
+        .filter(user.eq(_user))
+        .first::<LikeDB>(conn)?;
+
+    Ok((existing.to_like(), false))
+}
+
+fn remove_like(_blog_id: Uuid, _user: &str, conn: &mut DBPooledConnection) -> Result<(), Error> {
+    use crate::schema::likes::dsl::*;
+
+    diesel::delete(likes.filter(blog_id.eq(_blog_id)).filter(user.eq(_user))).execute(conn)?;
+
+    Ok(())
 }
 
 #[get("/blogs/{id}/likes")]
-async fn list(path: Path<(String,)>, pool: Data<DBPool>) -> HttpResponse {
-    let mut conn = pool.get().expect("Cannot connect to pool");
+async fn list(
+    path: Path<(String,)>,
+    query: Query<PageQuery>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
     let id: String = path.into_inner().0;
-    let blog_id = Uuid::from_str(&id).unwrap();
+    let blog_id = parse_blog_id(&id)?;
+    let limit = page_size(query.limit);
+    let cursor = parse_cursor(query.cursor.as_deref())?;
 
-    let likes = web::block(move || like_lists(blog_id, &mut conn))
-        .await
-        .unwrap()
-        .unwrap();
+    let likes = web::block(move || like_lists(blog_id, limit, cursor, &mut conn)).await??;
 
-    HttpResponse::Ok()
+    Ok(HttpResponse::Ok()
         .content_type("application/json")
-        .json(likes)
+        .json(likes))
 }
 
 #[post("/blogs/{id}/likes")]
-async fn like_blog(path: Path<(String,)>, pool: Data<DBPool>) -> HttpResponse {
-    let mut conn = pool.get().expect("Cannot connect to pool");
+async fn like_blog(
+    auth: JWTAuthToken,
+    path: Path<(String,)>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
     let id = path.into_inner().0;
-    let blog_id = Uuid::from_str(&id).unwrap();
+    let blog_id = parse_blog_id(&id)?;
 
-    let likes = web::block(move || add_like(blog_id, &mut conn))
-        .await
-        .unwrap()
-        .unwrap();
+    let (like, created) = web::block(move || add_like(blog_id, &auth.user, &mut conn))
+        .await?
+        .map_err(|err| match err {
+            Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+                AppError::NotFound("Blog not found".to_string())
+            }
+            err => AppError::from(err),
+        })?;
 
-    HttpResponse::Created()
-        .content_type("application/json")
-        .json(likes)
+    let mut response = if created {
+        HttpResponse::Created()
+    } else {
+        HttpResponse::Ok()
+    };
+
+    Ok(response.content_type("application/json").json(like))
 }
 
 #[delete("/blogs/{id}/likes")]
-async fn dislike_blog(path: Path<(String,)>, pool: Data<DBPool>) -> HttpResponse {
-    let mut conn = pool.get().expect("Cannot connect to pool");
+async fn dislike_blog(
+    auth: JWTAuthToken,
+    path: Path<(String,)>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
     let id = path.into_inner().0;
-    let blog_id = Uuid::from_str(&id).unwrap();
+    let blog_id = parse_blog_id(&id)?;
 
-    let _ = web::block(move || remove_like(blog_id, &mut conn));
+    web::block(move || remove_like(blog_id, &auth.user, &mut conn)).await??;
 
-    HttpResponse::NoContent()
+    Ok(HttpResponse::NoContent()
         .content_type("application/json")
-        .await
-        .unwrap()
+        .finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+
+    use crate::authorization::Role;
+    use crate::test_support::{
+        access_token, assert_unauthorized, bearer, cookie, create_blog, create_user, hs256_keys,
+        test_app, test_config, test_pool, TEST_SECRET,
+    };
+
+    const AUTHOR: &str = "author@example.com";
+    const READER: &str = "reader@example.com";
+
+    #[actix_web::test]
+    async fn liking_and_disliking_require_a_valid_token() {
+        let pool = test_pool();
+        let config = test_config(hs256_keys(TEST_SECRET));
+        let blog_id = {
+            let mut conn = pool.get().unwrap();
+            create_user(AUTHOR, Role::User, &mut conn);
+            create_user(READER, Role::User, &mut conn);
+            create_blog(AUTHOR, &mut conn)
+        };
+        let forged = access_token(
+            READER,
+            Role::User,
+            &test_config(hs256_keys(b"not the secret the server signs with")),
+        );
+        let app = test::init_service(test_app(&pool, &config)).await;
+
+        for method in [TestRequest::post, TestRequest::delete] {
+            let uri = format!("/blogs/{}/likes", blog_id);
+
+            let resp = test::call_service(&app, method().uri(&uri).to_request()).await;
+            let body = assert_unauthorized(resp).await;
+            assert_eq!(
+                body["message"],
+                "You are not logged in, please provide token"
+            );
+
+            for req in [bearer(method(), "not a token"), bearer(method(), &forged)] {
+                let resp = test::call_service(&app, req.uri(&uri).to_request()).await;
+                assert_unauthorized(resp).await;
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn liking_and_disliking_accept_bearer_tokens_and_cookies() {
+        let pool = test_pool();
+        let config = test_config(hs256_keys(TEST_SECRET));
+        let blog_id = {
+            let mut conn = pool.get().unwrap();
+            create_user(AUTHOR, Role::User, &mut conn);
+            create_user(READER, Role::User, &mut conn);
+            create_blog(AUTHOR, &mut conn)
+        };
+        let token = access_token(READER, Role::User, &config);
+        let app = test::init_service(test_app(&pool, &config)).await;
+        let uri = format!("/blogs/{}/likes", blog_id);
+
+        for authenticate in [bearer, cookie] {
+            let req = authenticate(TestRequest::post(), &token).uri(&uri);
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), StatusCode::CREATED);
+
+            let req = authenticate(TestRequest::delete(), &token).uri(&uri);
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        }
+    }
+
+    #[actix_web::test]
+    async fn likes_can_be_listed_without_a_token() {
+        let pool = test_pool();
+        let config = test_config(hs256_keys(TEST_SECRET));
+        let blog_id = {
+            let mut conn = pool.get().unwrap();
+            create_user(AUTHOR, Role::User, &mut conn);
+            create_blog(AUTHOR, &mut conn)
+        };
+        let app = test::init_service(test_app(&pool, &config)).await;
+
+        let req = TestRequest::get()
+            .uri(&format!("/blogs/{}/likes", blog_id))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
 }
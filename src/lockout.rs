@@ -0,0 +1,291 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{
+    Connection, ExpressionMethods, Insertable, OptionalExtension, QueryDsl, Queryable, RunQueryDsl,
+};
+
+use super::schema::login_failures;
+use crate::error::AppError;
+use crate::DBPooledConnection;
+
+/// Limits on failed logins, configured at startup.
+#[derive(Debug, Clone)]
+pub struct LoginThrottle {
+    pub max_account_failures: i32,
+    pub max_ip_failures: i32,
+    /// How long a lockout lasts in minutes. Failures older than this are
+    /// forgotten.
+    pub lockout_minutes: i64,
+}
+
+impl LoginThrottle {
+    fn window(&self) -> Duration {
+        Duration::minutes(self.lockout_minutes)
+    }
+
+    fn max_failures(&self, scope: LoginScope) -> i32 {
+        match scope {
+            LoginScope::Account => self.max_account_failures,
+            LoginScope::Ip => self.max_ip_failures,
+        }
+    }
+
+    /// Delay before the next attempt is allowed: one second after the first
+    /// failure, doubling with every further failure up to a quarter of the
+    /// window. Waiting out the backoff never lets the failures be forgotten,
+    /// so they keep counting towards the lockout.
+    fn backoff(&self, failures: i32) -> Duration {
+        Duration::seconds(1i64 << (failures - 1).clamp(0, 30)).min(self.window() / 4)
+    }
+
+    /// Whether `subject`s of `scope` can be locked out at all: failures are
+    /// forgotten once the window has passed, so every backoff before the
+    /// limit has to end inside it.
+    fn can_lock_out(&self, scope: LoginScope) -> bool {
+        (1..self.max_failures(scope)).all(|failures| self.backoff(failures) < self.window())
+    }
+
+    /// Rejects limits that could never be reached, naming the variable that
+    /// sets them.
+    pub fn validate(&self) -> Result<(), String> {
+        for (scope, name) in [
+            (LoginScope::Account, "LOGIN_MAX_ACCOUNT_FAILURES"),
+            (LoginScope::Ip, "LOGIN_MAX_IP_FAILURES"),
+        ] {
+            if !self.can_lock_out(scope) {
+                return Err(format!(
+                    "{} cannot be reached within LOGIN_LOCKOUT_MINUTES",
+                    name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What a failed login is counted against.
+#[derive(Debug, Clone, Copy)]
+pub enum LoginScope {
+    /// The email that was tried.
+    Account,
+    /// The address the request came from.
+    Ip,
+}
+
+impl LoginScope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LoginScope::Account => "account",
+            LoginScope::Ip => "ip",
+        }
+    }
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = login_failures)]
+pub struct LoginFailureDB {
+    pub scope: String,
+    pub subject: String,
+    pub failures: i32,
+    pub last_failure_at: NaiveDateTime,
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+fn too_many_attempts(until: NaiveDateTime, now: NaiveDateTime) -> AppError {
+    AppError::TooManyRequests(
+        "Too many failed login attempts, please try again later".to_string(),
+        (until - now).num_seconds().max(1) as u64,
+    )
+}
+
+/// Rejects a login attempt while `subject` is locked out or still waiting
+/// out the backoff after its last failure.
+pub fn check_login_allowed(
+    scope: LoginScope,
+    subject: &str,
+    throttle: &LoginThrottle,
+    conn: &mut DBPooledConnection,
+) -> Result<(), AppError> {
+    use crate::schema::login_failures::dsl;
+
+    let record = dsl::login_failures
+        .find((scope.as_str(), subject))
+        .first::<LoginFailureDB>(conn)
+        .optional()?;
+
+    let Some(record) = record else {
+        return Ok(());
+    };
+
+    let now = Utc::now().naive_utc();
+
+    if let Some(locked_until) = record.locked_until.filter(|until| *until > now) {
+        return Err(too_many_attempts(locked_until, now));
+    }
+
+    if record.last_failure_at + throttle.window() <= now {
+        return Ok(());
+    }
+
+    let next_attempt = record.last_failure_at + throttle.backoff(record.failures);
+    if next_attempt > now {
+        return Err(too_many_attempts(next_attempt, now));
+    }
+
+    Ok(())
+}
+
+/// Counts a failed login against `subject` and locks it out once it reaches
+/// the limit for its scope. Forgotten failures are purged along the way.
+pub fn record_login_failure(
+    scope: LoginScope,
+    subject: &str,
+    throttle: &LoginThrottle,
+    conn: &mut DBPooledConnection,
+) -> Result<(), AppError> {
+    use crate::schema::login_failures::dsl;
+
+    let now = Utc::now().naive_utc();
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        diesel::insert_into(dsl::login_failures)
+            .values(LoginFailureDB {
+                scope: scope.as_str().to_string(),
+                subject: subject.to_string(),
+                failures: 0,
+                last_failure_at: now,
+                locked_until: None,
+            })
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+
+        let record = dsl::login_failures
+            .find((scope.as_str(), subject))
+            .for_update()
+            .first::<LoginFailureDB>(conn)?;
+
+        let failures = if record.last_failure_at + throttle.window() <= now {
+            1
+        } else {
+            record.failures + 1
+        };
+        let locked_until =
+            (failures >= throttle.max_failures(scope)).then(|| now + throttle.window());
+
+        diesel::update(dsl::login_failures.find((scope.as_str(), subject)))
+            .set((
+                dsl::failures.eq(failures),
+                dsl::last_failure_at.eq(now),
+                dsl::locked_until.eq(locked_until),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    })?;
+
+    purge_expired_login_failures(throttle, conn)?;
+
+    Ok(())
+}
+
+/// Forgets the failures of `subject`, after a successful login or when an
+/// admin unlocks an account.
+pub fn clear_login_failures(
+    scope: LoginScope,
+    subject: &str,
+    conn: &mut DBPooledConnection,
+) -> Result<(), AppError> {
+    use crate::schema::login_failures::dsl;
+
+    diesel::delete(dsl::login_failures.find((scope.as_str(), subject))).execute(conn)?;
+
+    Ok(())
+}
+
+/// Removes failures older than the lockout window, which no longer count
+/// and whose lockouts have ended.
+pub fn purge_expired_login_failures(
+    throttle: &LoginThrottle,
+    conn: &mut DBPooledConnection,
+) -> Result<usize, AppError> {
+    use crate::schema::login_failures::dsl;
+
+    let cutoff = Utc::now().naive_utc() - throttle.window();
+    let purged = diesel::delete(dsl::login_failures.filter(dsl::last_failure_at.le(cutoff)))
+        .execute(conn)?;
+
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+
+    use super::*;
+    use crate::test_support::test_pool;
+
+    const CLIENT_IP: &str = "192.0.2.1";
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle {
+            max_account_failures: 5,
+            max_ip_failures: 20,
+            lockout_minutes: 15,
+        }
+    }
+
+    #[test]
+    fn every_limit_can_be_reached() {
+        for lockout_minutes in 1..=24 * 60 {
+            let throttle = LoginThrottle {
+                max_account_failures: 1000,
+                max_ip_failures: 1000,
+                lockout_minutes,
+            };
+
+            assert!(throttle.validate().is_ok(), "{} minutes", lockout_minutes);
+        }
+    }
+
+    #[test]
+    fn locks_out_an_ip_that_waits_out_every_backoff() {
+        let pool = test_pool();
+        let mut conn = pool.get().unwrap();
+        let throttle = throttle();
+
+        for failures in 1..=throttle.max_ip_failures {
+            assert!(
+                check_login_allowed(LoginScope::Ip, CLIENT_IP, &throttle, &mut conn).is_ok(),
+                "attempt {} was throttled",
+                failures
+            );
+            record_login_failure(LoginScope::Ip, CLIENT_IP, &throttle, &mut conn).unwrap();
+
+            // Pretend the client waited exactly as long as it had to.
+            diesel::update(login_failures::table.find((LoginScope::Ip.as_str(), CLIENT_IP)))
+                .set(
+                    login_failures::last_failure_at
+                        .eq(login_failures::last_failure_at - throttle.backoff(failures)),
+                )
+                .execute(&mut conn)
+                .unwrap();
+        }
+
+        let locked_until = login_failures::table
+            .find((LoginScope::Ip.as_str(), CLIENT_IP))
+            .select(login_failures::locked_until)
+            .first::<Option<NaiveDateTime>>(&mut conn)
+            .unwrap();
+        assert!(locked_until.is_some_and(|until| until > Utc::now().naive_utc()));
+
+        match check_login_allowed(LoginScope::Ip, CLIENT_IP, &throttle, &mut conn) {
+            Err(AppError::TooManyRequests(_, retry_after)) => {
+                assert!(
+                    retry_after > throttle.backoff(throttle.max_ip_failures).num_seconds() as u64
+                )
+            }
+            other => panic!("expected a lockout, got {:?}", other),
+        }
+    }
+}
@@ -1,51 +1,113 @@
-use actix_web::{middleware, web::Data, App, HttpServer};
+use actix_web::{
+    middleware,
+    web::{self, Data},
+    App, HttpServer,
+};
 use diesel::{r2d2::ConnectionManager, PgConnection};
 use dotenv::dotenv;
 
 use r2d2::{Pool, PooledConnection};
-use std::{env, io::Result};
+use std::{io::Result, process};
 
+mod admin;
+mod authorization;
+mod config;
+mod error;
+#[allow(non_snake_case)]
 mod jwtAuth;
 mod like;
+mod lockout;
+mod mail;
 mod microblog;
+mod password_reset;
+mod refresh_token;
 mod response;
+mod revision;
+mod revocation;
 mod schema;
+#[cfg(test)]
+mod test_support;
 mod token;
 mod user;
 mod validation;
+mod verification;
 
 pub type DBPool = Pool<ConnectionManager<PgConnection>>;
 pub type DBPooledConnection = PooledConnection<ConnectionManager<PgConnection>>;
 
 #[actix_rt::main]
 async fn main() -> Result<()> {
-    env::set_var("RUST_LOG", "actix_web=debug,actix_server=info");
-    env_logger::init();
-
     dotenv().ok();
 
-    let db_url = env::var("DATABASE_URL").expect("DATABASE_URL");
-    let db_conn = ConnectionManager::<PgConnection>::new(db_url);
+    let config = config::Config::from_env().unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {}", err);
+        process::exit(1);
+    });
+
+    env_logger::Builder::new()
+        .parse_filters(&config.log_level)
+        .init();
+
+    let db_conn = ConnectionManager::<PgConnection>::new(&config.database_url);
 
     let pool = r2d2::Pool::builder()
+        .max_size(config.pool_size)
         .build(db_conn)
         .expect("Failed to create pool");
 
+    let mailer = Data::from(mail::build_mailer(&config).unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {}", err);
+        process::exit(1);
+    }));
+
+    let bind_address = config.bind_address.clone();
+    let config = Data::new(config);
+
     HttpServer::new(move || {
         App::new()
             .app_data(Data::new(pool.clone()))
+            .app_data(config.clone())
+            .app_data(mailer.clone())
             .wrap(middleware::Logger::default())
-            .service(microblog::blogs)
-            .service(microblog::create_blogs)
-            .service(microblog::get_blog)
-            .service(microblog::delete_blog)
-            .service(like::list)
-            .service(like::like_blog)
-            .service(like::dislike_blog)
-            .service(user::register)
-            .service(user::login)
+            .configure(routes)
     })
-    .bind("127.0.0.1:8000")?
+    .bind(bind_address)?
     .run()
     .await
 }
+
+/// Registers every endpoint along with the extractor configuration, shared
+/// by the server and the endpoint tests.
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(
+        web::JsonConfig::default()
+            .error_handler(|err, _| error::AppError::Validation(err.to_string()).into()),
+    )
+    .app_data(
+        web::QueryConfig::default()
+            .error_handler(|err, _| error::AppError::Validation(err.to_string()).into()),
+    )
+    .service(microblog::blogs)
+    .service(microblog::create_blogs)
+    .service(microblog::get_blog)
+    .service(microblog::update_blog)
+    .service(microblog::delete_blog)
+    .service(revision::history)
+    .service(like::list)
+    .service(like::like_blog)
+    .service(like::dislike_blog)
+    .service(user::register)
+    .service(user::login)
+    .service(user::logout)
+    .service(user::me)
+    .service(user::update_me)
+    .service(user::update_password)
+    .service(verification::verify)
+    .service(verification::resend)
+    .service(password_reset::forgot)
+    .service(password_reset::reset)
+    .service(refresh_token::refresh)
+    .service(admin::users)
+    .service(admin::update_role)
+    .service(admin::unlock);
+}
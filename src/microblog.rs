@@ -1,22 +1,29 @@
 use actix_web::{
-    delete, get, post,
-    web::{self, Data, Json, Path},
+    delete, get, post, route,
+    web::{self, Data, Json, Path, Query},
     HttpResponse,
 };
 use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
-use diesel::query_dsl::methods::{FilterDsl, LimitDsl, OrderDsl};
 // use diesel::query_dsl::RunQueryDsl;
 use diesel::result::Error;
-use diesel::{ExpressionMethods, Insertable, Queryable, RunQueryDsl};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, Insertable, NullableExpressionMethods,
+    QueryDsl, Queryable, RunQueryDsl,
+};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use uuid::Uuid;
 
-use crate::like::{like_lists, Like};
-use crate::response::Response;
+use crate::authorization::{authorize_blog_owner, Role};
+use crate::error::AppError;
+use crate::jwtAuth::JWTAuthToken;
+use crate::like::{like_counts_for_blogs, like_lists_for_blogs, liked_blogs, Like};
+use crate::response::{page_size, parse_cursor, Cursor, Response};
+use crate::revision::{add_revision, RevisionDB};
+use crate::verification::require_verified_email;
 use crate::{DBPool, DBPooledConnection};
 
-use super::schema::microblogs;
+use super::schema::{microblogs, users};
 
 pub type MicroBlogs = Response<MicroBlog>;
 
@@ -25,7 +32,19 @@ pub struct MicroBlog {
     pub id: String,
     pub blog_message: String,
     pub created_at: DateTime<Utc>,
-    pub likes: Vec<Like>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub author: Option<BlogAuthor>,
+    pub like_count: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub liked_by_me: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub likes: Option<Vec<Like>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable)]
+pub struct BlogAuthor {
+    pub email: String,
+    pub username: String,
 }
 
 impl MicroBlog {
@@ -34,44 +53,47 @@ impl MicroBlog {
             id: Uuid::new_v4().to_string(),
             blog_message: blog,
             created_at: Utc::now(),
-            likes: vec![],
+            updated_at: None,
+            author: None,
+            like_count: 0,
+            liked_by_me: None,
+            likes: None,
         }
     }
 
-    pub fn to_db_microblog(&self) -> MicroBlogDB {
+    pub fn to_db_microblog(&self, author: &str) -> MicroBlogDB {
         let id = Uuid::from_str(self.id.as_ref()).unwrap();
         MicroBlogDB {
-            id: id,
+            id,
             blog_message: self.blog_message.clone(),
             created_at: Utc::now().naive_utc(),
-        }
-    }
-
-    pub fn add_likes(&self, likes: Vec<Like>) -> Self {
-        MicroBlog {
-            id: self.id.clone(),
-            blog_message: self.blog_message.clone(),
-            created_at: self.created_at,
-            likes: likes,
+            author: Some(author.to_string()),
+            updated_at: None,
         }
     }
 }
 
-#[diesel(table_name = microblogs)]
 #[derive(Queryable, Insertable)]
+#[diesel(table_name = microblogs)]
 pub struct MicroBlogDB {
     pub id: Uuid,
     pub blog_message: String,
     pub created_at: NaiveDateTime,
+    pub author: Option<String>,
+    pub updated_at: Option<NaiveDateTime>,
 }
 
 impl MicroBlogDB {
-    fn to_blog(&self) -> MicroBlog {
+    fn to_blog(&self, author: Option<BlogAuthor>) -> MicroBlog {
         MicroBlog {
             id: self.id.to_string(),
             blog_message: self.blog_message.to_string(),
             created_at: Utc.from_utc_datetime(&self.created_at),
-            likes: vec![],
+            updated_at: self.updated_at.map(|t| Utc.from_utc_datetime(&t)),
+            author,
+            like_count: 0,
+            liked_by_me: None,
+            likes: None,
         }
     }
 }
@@ -83,151 +105,535 @@ pub struct BlogRequest {
 
 impl BlogRequest {
     pub fn new_blog_request(&self) -> Option<MicroBlog> {
-        match &self.blog {
-            Some(a) => Some(MicroBlog::new(a.to_string())),
-            None => None,
-        }
+        self.blog.as_ref().map(|a| MicroBlog::new(a.to_string()))
     }
 }
 
-pub fn list_blogs(total_blogs: i64, conn: &mut DBPooledConnection) -> Result<MicroBlogs, Error> {
+#[derive(Debug, Deserialize)]
+pub struct BlogsQuery {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub include: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BlogQuery {
+    pub include: Option<String>,
+}
+
+/// Parses a blog id taken from the request path. An id that is not a valid
+/// uuid cannot name any blog, so it is reported as not found.
+pub fn parse_blog_id(id: &str) -> Result<Uuid, AppError> {
+    Uuid::from_str(id).map_err(|_| AppError::NotFound("Blog not found".to_string()))
+}
+
+/// Whether `?include=` asks for the full like list, e.g. `?include=likes`.
+fn includes_likes(include: &Option<String>) -> bool {
+    include
+        .as_deref()
+        .map(|i| i.split(',').any(|part| part.trim() == "likes"))
+        .unwrap_or(false)
+}
+
+/// Lists blogs newest first, starting after `cursor` when given. Ties on
+/// `created_at` are broken by `id` so pages never overlap or skip a blog.
+pub fn list_blogs(
+    total_blogs: i64,
+    cursor: Option<Cursor>,
+    conn: &mut DBPooledConnection,
+) -> Result<MicroBlogs, Error> {
     use crate::schema::microblogs::dsl::*;
 
-    let _blogs = match microblogs
-        .order(created_at.desc())
-        .limit(total_blogs)
-        .load::<MicroBlogDB>(conn)
-    {
-        Ok(res) => res,
-        Err(_) => vec![],
+    let mut query = microblogs
+        .left_join(users::table)
+        .select((
+            crate::schema::microblogs::all_columns,
+            (users::email, users::username).nullable(),
+        ))
+        .order((created_at.desc(), id.desc()))
+        .limit(total_blogs + 1)
+        .into_boxed();
+
+    if let Some(cursor) = cursor {
+        query = query.filter(
+            created_at
+                .lt(cursor.created_at)
+                .or(created_at.eq(cursor.created_at).and(id.lt(cursor.id))),
+        );
+    }
+
+    let mut _blogs = query.load::<(MicroBlogDB, Option<BlogAuthor>)>(conn)?;
+
+    let next_cursor = if _blogs.len() as i64 > total_blogs {
+        _blogs.truncate(total_blogs as usize);
+        _blogs.last().map(|(last, _)| {
+            Cursor {
+                created_at: last.created_at,
+                id: last.id,
+            }
+            .encode()
+        })
+    } else {
+        None
     };
 
     Ok(MicroBlogs {
         results: _blogs
             .into_iter()
-            .map(|t| t.to_blog())
+            .map(|(t, _author)| t.to_blog(_author))
             .collect::<Vec<MicroBlog>>(),
+        next_cursor,
     })
 }
 
-fn create_blog(blog_msg: MicroBlog, conn: &mut DBPooledConnection) -> Result<MicroBlog, ()> {
+/// Fills in like counts, `liked_by_me` for `viewer` and, when requested,
+/// the full like lists. Each is a single batched query over the whole `page`, so
+/// the number of queries does not grow with the page size.
+fn attach_likes(
+    page: &mut [MicroBlog],
+    include_likes: bool,
+    viewer: Option<&str>,
+    conn: &mut DBPooledConnection,
+) -> Result<(), Error> {
+    let blog_ids = page
+        .iter()
+        .filter_map(|b| Uuid::from_str(b.id.as_str()).ok())
+        .collect::<Vec<Uuid>>();
+
+    let counts = like_counts_for_blogs(&blog_ids, conn)?;
+    let liked = match viewer {
+        Some(viewer) => Some(liked_blogs(&blog_ids, viewer, conn)?),
+        None => None,
+    };
+    let mut likes = if include_likes {
+        Some(like_lists_for_blogs(&blog_ids, conn)?)
+    } else {
+        None
+    };
+
+    for blog in page.iter_mut() {
+        let Ok(blog_id) = Uuid::from_str(blog.id.as_str()) else {
+            continue;
+        };
+
+        blog.like_count = counts.get(&blog_id).copied().unwrap_or(0);
+        blog.liked_by_me = liked.as_ref().map(|liked| liked.contains(&blog_id));
+        blog.likes = likes
+            .as_mut()
+            .map(|likes| likes.remove(&blog_id).unwrap_or_default());
+    }
+
+    Ok(())
+}
+
+fn list_blogs_with_likes(
+    total_blogs: i64,
+    cursor: Option<Cursor>,
+    include_likes: bool,
+    viewer: Option<&str>,
+    conn: &mut DBPooledConnection,
+) -> Result<MicroBlogs, Error> {
+    let mut page = list_blogs(total_blogs, cursor, conn)?;
+    attach_likes(&mut page.results, include_likes, viewer, conn)?;
+
+    Ok(page)
+}
+
+fn get_blog_with_likes(
+    _id: Uuid,
+    include_likes: bool,
+    viewer: Option<&str>,
+    conn: &mut DBPooledConnection,
+) -> Result<MicroBlog, AppError> {
+    let mut blog = get_blog_by_uuid(_id, conn)?;
+    attach_likes(std::slice::from_mut(&mut blog), include_likes, viewer, conn)?;
+
+    Ok(blog)
+}
+
+fn create_blog(
+    blog_msg: MicroBlog,
+    _author: &str,
+    conn: &mut DBPooledConnection,
+) -> Result<MicroBlog, AppError> {
     use crate::schema::microblogs::dsl::*;
 
-    let blog_db = blog_msg.to_db_microblog();
-    let _ = diesel::insert_into(microblogs)
+    require_verified_email(_author, conn)?;
+
+    let blog_db = blog_msg.to_db_microblog(_author);
+    diesel::insert_into(microblogs)
         .values(&blog_db)
-        .execute(conn);
+        .execute(conn)?;
 
-    Ok(blog_db.to_blog())
+    get_blog_by_uuid(blog_db.id, conn)
 }
 
-fn get_blog_by_uuid(_id: Uuid, conn: &mut DBPooledConnection) -> Result<MicroBlog, Error> {
+fn get_blog_by_uuid(_id: Uuid, conn: &mut DBPooledConnection) -> Result<MicroBlog, AppError> {
     use crate::schema::microblogs::dsl::*;
 
-    let blog = microblogs.filter(id.eq(_id)).load::<MicroBlogDB>(conn);
-
-    match blog {
-        Ok(b) => match b.first() {
-            Some(first) => Ok(first.to_blog()),
-            _ => Err(Error::NotFound),
-        },
-        Err(err) => Err(err),
+    let blog = microblogs
+        .left_join(users::table)
+        .select((
+            crate::schema::microblogs::all_columns,
+            (users::email, users::username).nullable(),
+        ))
+        .filter(id.eq(_id))
+        .load::<(MicroBlogDB, Option<BlogAuthor>)>(conn)?;
+
+    match blog.into_iter().next() {
+        Some((first, _author)) => Ok(first.to_blog(_author)),
+        None => Err(AppError::NotFound("Blog not found".to_string())),
     }
 }
 
-fn delete_blog_by_uuid(_id: Uuid, conn: &mut DBPooledConnection) -> Result<(), Error> {
+/// Replaces the message of a blog owned by `user`, keeping the previous
+/// message as a revision.
+fn update_blog_by_uuid(
+    _id: Uuid,
+    message: String,
+    user: &str,
+    conn: &mut DBPooledConnection,
+) -> Result<MicroBlog, AppError> {
     use crate::schema::microblogs::dsl::*;
 
-    let blog = diesel::delete(microblogs.filter(id.eq(_id))).execute(conn);
+    authorize_blog_owner(_id, user, conn)?;
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        let current_message = microblogs
+            .filter(id.eq(_id))
+            .select(blog_message)
+            .for_update()
+            .first::<String>(conn)?;
+
+        if current_message != message {
+            add_revision(RevisionDB::new(_id, current_message), conn)?;
 
-    match blog {
-        Ok(_) => Ok(()),
-        Err(err) => Err(err),
+            diesel::update(microblogs.filter(id.eq(_id)))
+                .set(blog_message.eq(message))
+                .execute(conn)?;
+        }
+
+        Ok(())
+    })?;
+
+    get_blog_by_uuid(_id, conn)
+}
+
+/// Authors can delete their own blogs; moderators and admins can delete any.
+fn delete_blog_by_uuid(
+    _id: Uuid,
+    user: &str,
+    role: Role,
+    conn: &mut DBPooledConnection,
+) -> Result<(), AppError> {
+    use crate::schema::microblogs::dsl::*;
+
+    if role < Role::Moderator {
+        authorize_blog_owner(_id, user, conn)?;
+    }
+
+    match diesel::delete(microblogs.filter(id.eq(_id))).execute(conn)? {
+        0 => Err(AppError::NotFound("Blog not found".to_string())),
+        _ => Ok(()),
     }
 }
 
 #[get("/blogs")]
-async fn blogs(pool: Data<DBPool>) -> HttpResponse {
-    let mut conn = pool.get().expect("Cannot connect to pool");
-    let mut blogss = web::block(move || list_blogs(50, &mut conn))
-        .await
-        .unwrap()
-        .unwrap();
-
-    let mut conn = pool.get().expect("Cannot connect to pool");
-
-    let blogs = MicroBlogs {
-        results: blogss
-            .results
-            .iter_mut()
-            .map(|b| {
-                let likes = like_lists(Uuid::from_str(b.id.as_str()).unwrap(), &mut conn).unwrap();
-                b.add_likes(likes.results)
-            })
-            .collect::<Vec<MicroBlog>>(),
-    };
+async fn blogs(
+    auth: Option<JWTAuthToken>,
+    query: Query<BlogsQuery>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let query = query.into_inner();
+    let limit = page_size(query.limit);
+    let include_likes = includes_likes(&query.include);
+    let cursor = parse_cursor(query.cursor.as_deref())?;
+
+    let mut conn = pool.get()?;
+    let blogs = web::block(move || {
+        let viewer = auth.as_ref().map(|auth| auth.user.as_str());
+        list_blogs_with_likes(limit, cursor, include_likes, viewer, &mut conn)
+    })
+    .await??;
 
-    HttpResponse::Ok()
+    Ok(HttpResponse::Ok()
         .content_type("application/json")
-        .json(blogs)
+        .json(blogs))
 }
 
 #[post("/blogs")]
-async fn create_blogs(blog: Json<BlogRequest>, pool: Data<DBPool>) -> HttpResponse {
-    let mut conn = pool.get().expect("Cannot connect to DB pool");
+async fn create_blogs(
+    auth: JWTAuthToken,
+    blog: Json<BlogRequest>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let blog = blog
+        .new_blog_request()
+        .ok_or_else(|| AppError::Validation("Blog message is required".to_string()))?;
 
-    let blog = web::block(move || create_blog(blog.new_blog_request().unwrap(), &mut conn)).await;
+    let mut conn = pool.get()?;
 
-    match blog {
-        Ok(blog) => {
-            println!("{:?}", blog);
-            HttpResponse::Created()
-                .content_type("application/json")
-                .json(blog)
-        }
-        _ => HttpResponse::NoContent().await.unwrap(),
-    }
+    let blog = web::block(move || create_blog(blog, &auth.user, &mut conn)).await??;
+
+    Ok(HttpResponse::Created()
+        .content_type("application/json")
+        .json(blog))
 }
 
 #[get("/blogs/{id}")]
-async fn get_blog(path: Path<(String,)>, pool: Data<DBPool>) -> HttpResponse {
-    let mut conn = pool.get().expect("Cannot connect to DB pool");
+async fn get_blog(
+    auth: Option<JWTAuthToken>,
+    path: Path<(String,)>,
+    query: Query<BlogQuery>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
 
     let (id,): (String,) = path.into_inner();
-    // println!("{}", id);
+    let blog_id = parse_blog_id(&id)?;
+    let include_likes = includes_likes(&query.include);
 
-    let blog =
-        web::block(move || get_blog_by_uuid(Uuid::from_str(id.as_str()).unwrap(), &mut conn))
-            .await
-            .unwrap();
+    let blog = web::block(move || {
+        let viewer = auth.as_ref().map(|auth| auth.user.as_str());
+        get_blog_with_likes(blog_id, include_likes, viewer, &mut conn)
+    })
+    .await??;
 
-    println!("{:?}", blog);
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(blog))
+}
 
-    match blog {
-        Ok(blog) => HttpResponse::Ok()
-            .content_type("application/json")
-            .json(blog),
+#[route("/blogs/{id}", method = "PUT", method = "PATCH")]
+async fn update_blog(
+    auth: JWTAuthToken,
+    path: Path<(String,)>,
+    blog: Json<BlogRequest>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let (id,) = path.into_inner();
+    let blog_id = parse_blog_id(&id)?;
 
-        _ => HttpResponse::NoContent()
-            .content_type("application/json")
-            .await
-            .unwrap(),
-    }
+    let message = blog
+        .into_inner()
+        .blog
+        .ok_or_else(|| AppError::Validation("Blog message is required".to_string()))?;
+
+    let mut conn = pool.get()?;
+
+    let blog =
+        web::block(move || update_blog_by_uuid(blog_id, message, &auth.user, &mut conn)).await??;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(blog))
 }
 
 #[delete("/blogs/{id}")]
-async fn delete_blog(path: Path<(String,)>, pool: Data<DBPool>) -> HttpResponse {
-    let mut conn = pool.get().expect("Cannot connect to DB Pool");
-
+async fn delete_blog(
+    auth: JWTAuthToken,
+    path: Path<(String,)>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
     let (id,) = path.into_inner();
-    println!("{}", id);
+    let blog_id = parse_blog_id(&id)?;
 
-    let _ =
-        web::block(move || delete_blog_by_uuid(Uuid::from_str(id.as_str()).unwrap(), &mut conn))
-            .await;
+    let mut conn = pool.get()?;
 
-    HttpResponse::NoContent()
+    web::block(move || delete_blog_by_uuid(blog_id, &auth.user, auth.role, &mut conn)).await??;
+
+    Ok(HttpResponse::NoContent()
         .content_type("application/json")
-        .await
-        .unwrap()
+        .finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use diesel::connection::{Instrumentation, InstrumentationEvent};
+    use diesel::{Connection, RunQueryDsl};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::list_blogs_with_likes;
+    use crate::authorization::Role;
+    use crate::like::Like;
+    use crate::schema::likes;
+    use crate::test_support::{
+        access_token, assert_unauthorized, bearer, cookie, create_blog, create_user, hs256_keys,
+        test_app, test_config, test_pool, TEST_SECRET,
+    };
+
+    const AUTHOR: &str = "author@example.com";
+    const READER: &str = "reader@example.com";
+
+    /// Counts the statements sent over the connection it is attached to.
+    fn statement_counter() -> (Arc<AtomicUsize>, impl Instrumentation) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+
+        (count, move |event: InstrumentationEvent<'_>| {
+            if matches!(event, InstrumentationEvent::StartQuery { .. }) {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        })
+    }
+
+    /// Requests that must not get past the authentication check: no token,
+    /// a malformed token and a token signed with someone else's key.
+    fn unauthenticated(req: fn() -> TestRequest) -> Vec<TestRequest> {
+        let forged = access_token(
+            AUTHOR,
+            Role::User,
+            &test_config(hs256_keys(b"not the secret the server signs with")),
+        );
+
+        vec![req(), bearer(req(), "not a token"), bearer(req(), &forged)]
+    }
+
+    #[actix_web::test]
+    async fn create_blogs_requires_a_valid_token() {
+        let pool = test_pool();
+        let config = test_config(hs256_keys(TEST_SECRET));
+        create_user(AUTHOR, Role::User, &mut pool.get().unwrap());
+        let app = test::init_service(test_app(&pool, &config)).await;
+
+        let resp = test::call_service(
+            &app,
+            TestRequest::post()
+                .uri("/blogs")
+                .set_json(json!({ "blog": "Hello" }))
+                .to_request(),
+        )
+        .await;
+        let body = assert_unauthorized(resp).await;
+        assert_eq!(
+            body["message"],
+            "You are not logged in, please provide token"
+        );
+
+        for req in unauthenticated(TestRequest::post) {
+            let req = req
+                .uri("/blogs")
+                .set_json(json!({ "blog": "Hello" }))
+                .to_request();
+            assert_unauthorized(test::call_service(&app, req).await).await;
+        }
+    }
+
+    #[actix_web::test]
+    async fn create_blogs_accepts_bearer_tokens_and_cookies() {
+        let pool = test_pool();
+        let config = test_config(hs256_keys(TEST_SECRET));
+        create_user(AUTHOR, Role::User, &mut pool.get().unwrap());
+        let token = access_token(AUTHOR, Role::User, &config);
+        let app = test::init_service(test_app(&pool, &config)).await;
+
+        for req in [
+            bearer(TestRequest::post(), &token),
+            cookie(TestRequest::post(), &token),
+        ] {
+            let req = req
+                .uri("/blogs")
+                .set_json(json!({ "blog": "Hello" }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::CREATED);
+        }
+    }
+
+    #[actix_web::test]
+    async fn delete_blog_requires_a_valid_token() {
+        let pool = test_pool();
+        let config = test_config(hs256_keys(TEST_SECRET));
+        let blog_id = {
+            let mut conn = pool.get().unwrap();
+            create_user(AUTHOR, Role::User, &mut conn);
+            create_blog(AUTHOR, &mut conn)
+        };
+        let app = test::init_service(test_app(&pool, &config)).await;
+
+        for req in unauthenticated(TestRequest::delete) {
+            let req = req.uri(&format!("/blogs/{}", blog_id)).to_request();
+            assert_unauthorized(test::call_service(&app, req).await).await;
+        }
+    }
+
+    #[actix_web::test]
+    async fn delete_blog_accepts_bearer_tokens_and_cookies() {
+        let pool = test_pool();
+        let config = test_config(hs256_keys(TEST_SECRET));
+        let blog_ids = {
+            let mut conn = pool.get().unwrap();
+            create_user(AUTHOR, Role::User, &mut conn);
+            [
+                create_blog(AUTHOR, &mut conn),
+                create_blog(AUTHOR, &mut conn),
+            ]
+        };
+        let token = access_token(AUTHOR, Role::User, &config);
+        let app = test::init_service(test_app(&pool, &config)).await;
+
+        for (req, blog_id) in [
+            bearer(TestRequest::delete(), &token),
+            cookie(TestRequest::delete(), &token),
+        ]
+        .into_iter()
+        .zip(blog_ids)
+        {
+            let req = req.uri(&format!("/blogs/{}", blog_id)).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        }
+    }
+
+    #[actix_web::test]
+    async fn blogs_can_be_read_without_a_token() {
+        let pool = test_pool();
+        let config = test_config(hs256_keys(TEST_SECRET));
+        let blog_id = {
+            let mut conn = pool.get().unwrap();
+            create_user(AUTHOR, Role::User, &mut conn);
+            create_blog(AUTHOR, &mut conn)
+        };
+        let app = test::init_service(test_app(&pool, &config)).await;
+
+        for uri in ["/blogs".to_string(), format!("/blogs/{}", blog_id)] {
+            let req = TestRequest::get().uri(&uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{}", uri);
+        }
+    }
+
+    #[test]
+    fn listing_blogs_runs_the_same_statements_for_any_page_size() {
+        let pool = test_pool();
+        let mut conn = pool.get().unwrap();
+        create_user(AUTHOR, Role::User, &mut conn);
+        create_user(READER, Role::User, &mut conn);
+        for _ in 0..5 {
+            let blog_id = create_blog(AUTHOR, &mut conn);
+            diesel::insert_into(likes::table)
+                .values(Like::new(READER).to_db_likes(blog_id))
+                .execute(&mut conn)
+                .unwrap();
+        }
+
+        let (count, counter) = statement_counter();
+        conn.set_instrumentation(counter);
+
+        let mut statements = Vec::new();
+        for page_size in [1, 5] {
+            count.store(0, Ordering::SeqCst);
+            let page =
+                list_blogs_with_likes(page_size, None, true, Some(READER), &mut conn).unwrap();
+            assert_eq!(page.results.len(), page_size as usize);
+            statements.push(count.load(Ordering::SeqCst));
+        }
+
+        assert!(statements[0] > 0);
+        assert_eq!(statements[0], statements[1]);
+    }
 }
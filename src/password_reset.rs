@@ -0,0 +1,265 @@
+use actix_web::{
+    post, rt,
+    web::{self, Data, Json},
+    HttpResponse,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{
+    Connection, ExpressionMethods, Insertable, OptionalExtension, QueryDsl, Queryable, RunQueryDsl,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::schema::{password_reset_tokens, users};
+use crate::config::Config;
+use crate::error::AppError;
+use crate::lockout::{clear_login_failures, LoginScope};
+use crate::mail::{Email, Mailer};
+use crate::response::StatusResponse;
+use crate::revocation::revoke_all_user_tokens;
+use crate::token::{hash_opaque_token, new_opaque_token, opaque_token_uuid};
+use crate::user::{hash_password, UserDB};
+use crate::validation::normalize_email;
+use crate::{DBPool, DBPooledConnection};
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = password_reset_tokens)]
+pub struct PasswordResetTokenDB {
+    pub token_uuid: Uuid,
+    pub user_email: String,
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+fn invalid_reset_token() -> AppError {
+    AppError::Validation("Invalid or expired password reset token".to_string())
+}
+
+/// Stores a reset token for the account and mails it. Unknown emails are
+/// ignored without a trace.
+fn request_password_reset(
+    requested_email: &str,
+    config: &Config,
+    mailer: &dyn Mailer,
+    conn: &mut DBPooledConnection,
+) -> Result<(), AppError> {
+    use crate::schema::password_reset_tokens::dsl::*;
+
+    let Some(user) = normalize_email(requested_email) else {
+        return Ok(());
+    };
+
+    let user_exists =
+        diesel::select(diesel::dsl::exists(users::table.find(&user))).get_result::<bool>(conn)?;
+    if !user_exists {
+        return Ok(());
+    }
+
+    let (_token_uuid, token) = new_opaque_token();
+    let now = Utc::now().naive_utc();
+
+    diesel::insert_into(password_reset_tokens)
+        .values(PasswordResetTokenDB {
+            token_uuid: _token_uuid,
+            user_email: user.to_string(),
+            token_hash: hash_opaque_token(&token),
+            created_at: now,
+            expires_at: now + Duration::minutes(config.password_reset_max_age),
+            used_at: None,
+        })
+        .execute(conn)?;
+
+    purge_expired_password_reset_tokens(conn)?;
+
+    mailer.send(&Email {
+        to: user,
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "To choose a new password, send this token with your new password to {}/password/reset:\n\n{}\n\nThe token expires in {} minutes. If you did not ask to reset your password you can ignore this email.",
+            config.public_url, token, config.password_reset_max_age
+        ),
+    })
+}
+
+/// Removes reset tokens that have expired, used or not, as they can no
+/// longer be redeemed.
+pub fn purge_expired_password_reset_tokens(
+    conn: &mut DBPooledConnection,
+) -> Result<usize, AppError> {
+    use crate::schema::password_reset_tokens::dsl::*;
+
+    let purged =
+        diesel::delete(password_reset_tokens.filter(expires_at.lt(Utc::now().naive_utc())))
+            .execute(conn)?;
+
+    Ok(purged)
+}
+
+/// Sets a new password using a reset token. Using a token uses up every
+/// outstanding token of the account and logs the user out everywhere.
+fn reset_password(
+    data: ResetPasswordRequest,
+    config: &Config,
+    conn: &mut DBPooledConnection,
+) -> Result<(), AppError> {
+    use crate::schema::password_reset_tokens::dsl::*;
+
+    let _token_uuid = opaque_token_uuid(&data.token).ok_or_else(invalid_reset_token)?;
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        let stored = password_reset_tokens
+            .find(_token_uuid)
+            .for_update()
+            .first::<PasswordResetTokenDB>(conn)
+            .optional()?
+            .ok_or_else(invalid_reset_token)?;
+
+        let now = Utc::now().naive_utc();
+        if stored.token_hash != hash_opaque_token(&data.token)
+            || stored.used_at.is_some()
+            || stored.expires_at <= now
+        {
+            return Err(invalid_reset_token());
+        }
+
+        let user = users::table
+            .find(&stored.user_email)
+            .first::<UserDB>(conn)?;
+
+        let violations =
+            config
+                .password_policy
+                .violations(&data.password, &user.email, &user.username);
+        if !violations.is_empty() {
+            return Err(AppError::ValidationErrors(
+                "Password does not meet the requirements".to_string(),
+                violations,
+            ));
+        }
+
+        // The reset link went to this address, which proves the user owns it.
+        diesel::update(users::table.find(&user.email))
+            .set((
+                users::password.eq(hash_password(&data.password)?),
+                users::email_verified_at.eq(user.email_verified_at.or(Some(now))),
+            ))
+            .execute(conn)?;
+
+        diesel::update(
+            password_reset_tokens
+                .filter(user_email.eq(&user.email))
+                .filter(used_at.is_null()),
+        )
+        .set(used_at.eq(now))
+        .execute(conn)?;
+
+        revoke_all_user_tokens(&user.email, conn)?;
+        clear_login_failures(LoginScope::Account, &user.email, conn)?;
+
+        Ok(())
+    })
+}
+
+/// Always answers 202, whether or not the account exists. The token is
+/// created and mailed after the response has been sent, so the response
+/// time does not give the answer away either.
+#[post("/password/forgot")]
+async fn forgot(
+    data: Json<ForgotPasswordRequest>,
+    config: Data<Config>,
+    mailer: Data<dyn Mailer>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    rt::spawn(async move {
+        let result = web::block(move || {
+            let mut conn = pool.get()?;
+            request_password_reset(&data.email, &config, mailer.as_ref(), &mut conn)
+        })
+        .await;
+
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => println!("Failed to send password reset email: {}", err),
+            Err(err) => println!("Failed to send password reset email: {}", err),
+        }
+    });
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[post("/password/reset")]
+async fn reset(
+    data: Json<ResetPasswordRequest>,
+    config: Data<Config>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
+
+    web::block(move || reset_password(data.into_inner(), &config, &mut conn)).await??;
+
+    Ok(HttpResponse::Ok().json(StatusResponse {
+        status: "SUCCESS".to_string(),
+        message: "Password has been reset, please log in again".to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+    use uuid::Uuid;
+
+    use super::{request_password_reset, PasswordResetTokenDB};
+    use crate::authorization::Role;
+    use crate::mail::build_mailer;
+    use crate::schema::password_reset_tokens;
+    use crate::test_support::{create_user, hs256_keys, test_config, test_pool, TEST_SECRET};
+
+    const USER: &str = "forgetful@example.com";
+
+    #[test]
+    fn requesting_a_reset_purges_expired_tokens() {
+        let pool = test_pool();
+        let config = test_config(hs256_keys(TEST_SECRET));
+        let mailer = build_mailer(&config).unwrap();
+        let mut conn = pool.get().unwrap();
+        create_user(USER, Role::User, &mut conn);
+
+        let expired = Uuid::new_v4();
+        let long_ago = Utc::now().naive_utc() - Duration::days(1);
+        diesel::insert_into(password_reset_tokens::table)
+            .values(PasswordResetTokenDB {
+                token_uuid: expired,
+                user_email: USER.to_string(),
+                token_hash: String::new(),
+                created_at: long_ago,
+                expires_at: long_ago + Duration::minutes(config.password_reset_max_age),
+                used_at: None,
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        request_password_reset(USER, &config, mailer.as_ref(), &mut conn).unwrap();
+
+        let stored = password_reset_tokens::table
+            .filter(password_reset_tokens::user_email.eq(USER))
+            .select(password_reset_tokens::token_uuid)
+            .load::<Uuid>(&mut conn)
+            .unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_ne!(stored[0], expired);
+    }
+}
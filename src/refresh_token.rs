@@ -0,0 +1,290 @@
+use actix_web::{
+    post,
+    web::{self, Data, Json},
+    HttpResponse,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{
+    Connection, ExpressionMethods, Insertable, OptionalExtension, QueryDsl, Queryable, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::schema::{refresh_tokens, users};
+use crate::authorization::Role;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::jwtAuth::auth_cookies;
+use crate::token::{generate_jwt_token, hash_opaque_token, new_opaque_token, opaque_token_uuid};
+use crate::{DBPool, DBPooledConnection};
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = refresh_tokens)]
+pub struct RefreshTokenDB {
+    pub token_uuid: Uuid,
+    pub family_id: Uuid,
+    pub user_email: String,
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub rotated_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+/// Returned by login and refresh. `message` still carries the access token
+/// for clients that read it from the old `StatusResponse` shape.
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub status: String,
+    pub message: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_in: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+enum Rotation {
+    Rotated(TokenResponse),
+    Reused,
+}
+
+fn invalid_refresh_token() -> AppError {
+    AppError::Unauthorized("Invalid refresh token".to_string())
+}
+
+/// Creates a refresh token of the form `<token_uuid>.<secret>` in the given
+/// token family and stores its hash.
+fn new_refresh_token(
+    user: &str,
+    family: Uuid,
+    ttl: i64,
+    conn: &mut DBPooledConnection,
+) -> Result<String, AppError> {
+    use crate::schema::refresh_tokens::dsl::*;
+
+    let (_token_uuid, token) = new_opaque_token();
+
+    let now = Utc::now().naive_utc();
+    diesel::insert_into(refresh_tokens)
+        .values(RefreshTokenDB {
+            token_uuid: _token_uuid,
+            family_id: family,
+            user_email: user.to_string(),
+            token_hash: hash_opaque_token(&token),
+            created_at: now,
+            expires_at: now + Duration::minutes(ttl),
+            rotated_at: None,
+            revoked_at: None,
+        })
+        .execute(conn)?;
+
+    purge_expired_refresh_tokens(conn)?;
+
+    Ok(token)
+}
+
+/// Removes refresh tokens that have expired, as those can no longer be
+/// exchanged or reused anyway.
+pub fn purge_expired_refresh_tokens(conn: &mut DBPooledConnection) -> Result<usize, AppError> {
+    use crate::schema::refresh_tokens::dsl::*;
+
+    let purged = diesel::delete(refresh_tokens.filter(expires_at.lt(Utc::now().naive_utc())))
+        .execute(conn)?;
+
+    Ok(purged)
+}
+
+/// Issues an access token and a refresh token for `user`. A new token family
+/// is started unless `family` is given. The role is read from the user's row
+/// each time, so a role change takes effect on the next refresh.
+pub fn issue_tokens(
+    user: String,
+    family: Option<Uuid>,
+    config: &Config,
+    conn: &mut DBPooledConnection,
+) -> Result<TokenResponse, AppError> {
+    let refresh_token = new_refresh_token(
+        &user,
+        family.unwrap_or_else(Uuid::new_v4),
+        config.refresh_token_max_age,
+        conn,
+    )?;
+    let role = users::table
+        .find(&user)
+        .select(users::role)
+        .first::<String>(conn)?
+        .parse::<Role>()?;
+    let token = generate_jwt_token(user, role, config)?;
+    let access_token = token.access_token.unwrap_or_default();
+
+    Ok(TokenResponse {
+        status: "SUCCESS".to_string(),
+        message: access_token.clone(),
+        access_token,
+        refresh_token,
+        expires_in: token.expires_in,
+    })
+}
+
+/// Exchanges a refresh token for a new token pair. Each refresh token can be
+/// used once; presenting it again revokes every token in its family.
+fn rotate_refresh_token(
+    presented: &str,
+    config: &Config,
+    conn: &mut DBPooledConnection,
+) -> Result<TokenResponse, AppError> {
+    use crate::schema::refresh_tokens::dsl::*;
+
+    let _token_uuid = opaque_token_uuid(presented).ok_or_else(invalid_refresh_token)?;
+
+    let rotation = conn.transaction::<_, AppError, _>(|conn| {
+        let stored = refresh_tokens
+            .find(_token_uuid)
+            .for_update()
+            .first::<RefreshTokenDB>(conn)
+            .optional()?
+            .ok_or_else(invalid_refresh_token)?;
+
+        if stored.token_hash != hash_opaque_token(presented) {
+            return Err(invalid_refresh_token());
+        }
+
+        let now = Utc::now().naive_utc();
+
+        if stored.rotated_at.is_some() || stored.revoked_at.is_some() {
+            diesel::update(
+                refresh_tokens
+                    .filter(family_id.eq(stored.family_id))
+                    .filter(revoked_at.is_null()),
+            )
+            .set(revoked_at.eq(now))
+            .execute(conn)?;
+
+            return Ok(Rotation::Reused);
+        }
+
+        if stored.expires_at <= now {
+            return Err(AppError::Unauthorized(
+                "Refresh token has expired".to_string(),
+            ));
+        }
+
+        diesel::update(refresh_tokens.find(_token_uuid))
+            .set(rotated_at.eq(now))
+            .execute(conn)?;
+
+        let tokens = issue_tokens(stored.user_email, Some(stored.family_id), config, conn)?;
+        Ok(Rotation::Rotated(tokens))
+    })?;
+
+    match rotation {
+        Rotation::Rotated(tokens) => Ok(tokens),
+        Rotation::Reused => Err(AppError::Unauthorized(
+            "Refresh token has been revoked".to_string(),
+        )),
+    }
+}
+
+/// Revokes every token in the family of `presented`, as long as it belongs to
+/// `user`. Unknown tokens are ignored.
+pub fn revoke_refresh_token_family(
+    presented: &str,
+    user: &str,
+    conn: &mut DBPooledConnection,
+) -> Result<(), AppError> {
+    use crate::schema::refresh_tokens::dsl::*;
+
+    let Some(_token_uuid) = opaque_token_uuid(presented) else {
+        return Ok(());
+    };
+
+    let stored = refresh_tokens
+        .find(_token_uuid)
+        .first::<RefreshTokenDB>(conn)
+        .optional()?;
+
+    if let Some(stored) = stored {
+        if stored.user_email == user && stored.token_hash == hash_opaque_token(presented) {
+            diesel::update(
+                refresh_tokens
+                    .filter(family_id.eq(stored.family_id))
+                    .filter(revoked_at.is_null()),
+            )
+            .set(revoked_at.eq(Utc::now().naive_utc()))
+            .execute(conn)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[post("/token/refresh")]
+async fn refresh(
+    data: Json<RefreshRequest>,
+    config: Data<Config>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
+
+    let ttl = config.access_token_max_age;
+    let tokens =
+        web::block(move || rotate_refresh_token(&data.refresh_token, &config, &mut conn)).await??;
+    let (access_cookie, csrf_cookie) = auth_cookies(&tokens.access_token, ttl);
+
+    Ok(HttpResponse::Ok()
+        .cookie(access_cookie)
+        .cookie(csrf_cookie)
+        .json(tokens))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+    use uuid::Uuid;
+
+    use super::{issue_tokens, RefreshTokenDB};
+    use crate::authorization::Role;
+    use crate::schema::refresh_tokens;
+    use crate::test_support::{create_user, hs256_keys, test_config, test_pool, TEST_SECRET};
+
+    const USER: &str = "refresh@example.com";
+
+    #[test]
+    fn issuing_tokens_purges_expired_refresh_tokens() {
+        let pool = test_pool();
+        let config = test_config(hs256_keys(TEST_SECRET));
+        let mut conn = pool.get().unwrap();
+        create_user(USER, Role::User, &mut conn);
+
+        let expired = Uuid::new_v4();
+        let long_ago = Utc::now().naive_utc() - Duration::days(30);
+        diesel::insert_into(refresh_tokens::table)
+            .values(RefreshTokenDB {
+                token_uuid: expired,
+                family_id: Uuid::new_v4(),
+                user_email: USER.to_string(),
+                token_hash: String::new(),
+                created_at: long_ago,
+                expires_at: long_ago + Duration::minutes(config.refresh_token_max_age),
+                rotated_at: None,
+                revoked_at: None,
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        issue_tokens(USER.to_string(), None, &config, &mut conn).unwrap();
+
+        let stored = refresh_tokens::table
+            .filter(refresh_tokens::user_email.eq(USER))
+            .select(refresh_tokens::token_uuid)
+            .load::<Uuid>(&mut conn)
+            .unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_ne!(stored[0], expired);
+    }
+}
@@ -1,8 +1,16 @@
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, NaiveDateTime};
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use uuid::Uuid;
+
+use crate::error::AppError;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Response<T> {
     pub results: Vec<T>,
+    #[serde(default)]
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -10,3 +18,56 @@ pub struct StatusResponse {
     pub status: String,
     pub message: String,
 }
+
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+pub fn page_size(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+}
+
+/// Position in a list ordered by `created_at` and `id`, both descending.
+/// Clients only ever see it as an opaque string.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cursor {
+    pub created_at: NaiveDateTime,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let raw = format!(
+            "{}:{}",
+            self.created_at.and_utc().timestamp_micros(),
+            self.id
+        );
+        general_purpose::URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let bytes = general_purpose::URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        let raw = String::from_utf8(bytes).ok()?;
+        let (micros, id) = raw.split_once(':')?;
+
+        Some(Cursor {
+            created_at: DateTime::from_timestamp_micros(micros.parse().ok()?)?.naive_utc(),
+            id: Uuid::from_str(id).ok()?,
+        })
+    }
+}
+
+/// Decodes the optional `?cursor=` query parameter.
+pub fn parse_cursor(cursor: Option<&str>) -> Result<Option<Cursor>, AppError> {
+    match cursor {
+        None => Ok(None),
+        Some(cursor) => Cursor::decode(cursor)
+            .map(Some)
+            .ok_or_else(|| AppError::Validation("Invalid cursor".to_string())),
+    }
+}
@@ -0,0 +1,104 @@
+use actix_web::web::{self, Data, Path};
+use actix_web::{get, HttpResponse};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use diesel::prelude::*;
+use diesel::result::Error;
+use diesel::{ExpressionMethods, Insertable, Queryable, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::schema::microblog_revisions;
+use crate::error::AppError;
+use crate::microblog::parse_blog_id;
+use crate::DBPool;
+use crate::{response::Response, DBPooledConnection};
+
+pub type Revisions = Response<Revision>;
+
+/// A previous version of a blog message, recorded whenever the blog is edited.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Revision {
+    pub id: String,
+    pub blog_message: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = microblog_revisions)]
+pub struct RevisionDB {
+    pub id: Uuid,
+    pub blog_id: Uuid,
+    pub blog_message: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl RevisionDB {
+    pub fn new(blog_id: Uuid, blog_message: String) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            blog_id,
+            blog_message,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    pub fn to_revision(&self) -> Revision {
+        Revision {
+            id: self.id.to_string(),
+            blog_message: self.blog_message.to_string(),
+            created_at: Utc.from_utc_datetime(&self.created_at),
+        }
+    }
+}
+
+pub fn add_revision(revision: RevisionDB, conn: &mut DBPooledConnection) -> Result<(), Error> {
+    use crate::schema::microblog_revisions::dsl::*;
+
+    diesel::insert_into(microblog_revisions)
+        .values(revision)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+pub fn revision_lists(
+    _blog_id: Uuid,
+    conn: &mut DBPooledConnection,
+) -> Result<Revisions, AppError> {
+    use crate::schema::microblog_revisions::dsl::*;
+
+    let blog_exists = diesel::select(diesel::dsl::exists(
+        crate::schema::microblogs::table.find(_blog_id),
+    ))
+    .get_result::<bool>(conn)?;
+
+    if !blog_exists {
+        return Err(AppError::NotFound("Blog not found".to_string()));
+    }
+
+    let _revisions = microblog_revisions
+        .filter(blog_id.eq(_blog_id))
+        .order(created_at.desc())
+        .load::<RevisionDB>(conn)?;
+
+    Ok(Revisions {
+        results: _revisions
+            .into_iter()
+            .map(|r| r.to_revision())
+            .collect::<Vec<Revision>>(),
+        next_cursor: None,
+    })
+}
+
+#[get("/blogs/{id}/history")]
+async fn history(path: Path<(String,)>, pool: Data<DBPool>) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
+    let id: String = path.into_inner().0;
+    let blog_id = parse_blog_id(&id)?;
+
+    let revisions = web::block(move || revision_lists(blog_id, &mut conn)).await??;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .json(revisions))
+}
@@ -0,0 +1,146 @@
+use chrono::{NaiveDateTime, SubsecRound, TimeZone, Utc};
+use diesel::{ExpressionMethods, Insertable, OptionalExtension, QueryDsl, Queryable, RunQueryDsl};
+use uuid::Uuid;
+
+use super::schema::{refresh_tokens, revoked_tokens, users};
+use crate::error::AppError;
+use crate::DBPooledConnection;
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = revoked_tokens)]
+pub struct RevokedTokenDB {
+    pub token_uuid: Uuid,
+    pub user_email: String,
+    pub revoked_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+/// Adds an access token to the revocation list until `exp`, the expiry
+/// timestamp from its claims. Expired entries are purged along the way.
+pub fn revoke_access_token(
+    _token_uuid: Uuid,
+    user: &str,
+    exp: i64,
+    conn: &mut DBPooledConnection,
+) -> Result<(), AppError> {
+    use crate::schema::revoked_tokens::dsl::*;
+
+    let expiry = Utc
+        .timestamp_opt(exp, 0)
+        .single()
+        .map(|t| t.naive_utc())
+        .unwrap_or_else(|| Utc::now().naive_utc());
+
+    diesel::insert_into(revoked_tokens)
+        .values(RevokedTokenDB {
+            token_uuid: _token_uuid,
+            user_email: user.to_string(),
+            revoked_at: Utc::now().naive_utc(),
+            expires_at: expiry,
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    purge_expired_revocations(conn)?;
+
+    Ok(())
+}
+
+/// Whether an access token has been revoked, either by itself or because
+/// all tokens of `user` issued before its `tokens_invalid_before` were.
+/// `issued_at_us` is the token's issue time in microseconds.
+pub fn is_token_revoked(
+    _token_uuid: Uuid,
+    user: &str,
+    issued_at_us: i64,
+    conn: &mut DBPooledConnection,
+) -> Result<bool, AppError> {
+    use crate::schema::revoked_tokens::dsl::*;
+
+    let revoked = diesel::select(diesel::dsl::exists(revoked_tokens.find(_token_uuid)))
+        .get_result::<bool>(conn)?;
+    if revoked {
+        return Ok(true);
+    }
+
+    let invalid_before = users::table
+        .find(user)
+        .select(users::tokens_invalid_before)
+        .first::<Option<NaiveDateTime>>(conn)
+        .optional()?
+        .flatten();
+
+    Ok(invalid_before.is_some_and(|before| issued_at_us < before.and_utc().timestamp_micros()))
+}
+
+/// Logs `user` out everywhere: every access token issued so far is rejected
+/// and every refresh token is revoked.
+pub fn revoke_all_user_tokens(user: &str, conn: &mut DBPooledConnection) -> Result<(), AppError> {
+    let now = Utc::now().naive_utc();
+
+    // Tokens carry their issue time in microseconds, which is also what the
+    // column stores. Truncating rather than letting the database round means
+    // a token issued right after this call is never mistaken for an older one.
+    let cutoff = now.trunc_subsecs(6);
+
+    diesel::update(users::table.find(user))
+        .set(users::tokens_invalid_before.eq(cutoff))
+        .execute(conn)?;
+
+    diesel::update(
+        refresh_tokens::table
+            .filter(refresh_tokens::user_email.eq(user))
+            .filter(refresh_tokens::revoked_at.is_null()),
+    )
+    .set(refresh_tokens::revoked_at.eq(now))
+    .execute(conn)?;
+
+    Ok(())
+}
+
+/// Removes entries for tokens that have expired, as those are rejected by
+/// signature validation anyway.
+pub fn purge_expired_revocations(conn: &mut DBPooledConnection) -> Result<usize, AppError> {
+    use crate::schema::revoked_tokens::dsl::*;
+
+    let purged = diesel::delete(revoked_tokens.filter(expires_at.lt(Utc::now().naive_utc())))
+        .execute(conn)?;
+
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authorization::Role;
+    use crate::refresh_token::issue_tokens;
+    use crate::test_support::{create_user, hs256_keys, test_config, test_pool, TEST_SECRET};
+    use crate::token::verify_jwt_token;
+
+    const USER: &str = "revoked@example.com";
+
+    #[test]
+    fn tokens_issued_right_after_a_revocation_work_without_leeway() {
+        let pool = test_pool();
+        let mut config = test_config(hs256_keys(TEST_SECRET));
+        config.token_leeway = 0;
+        let mut conn = pool.get().unwrap();
+        create_user(USER, Role::User, &mut conn);
+
+        for _ in 0..20 {
+            let before = issue_tokens(USER.to_string(), None, &config, &mut conn).unwrap();
+            revoke_all_user_tokens(USER, &mut conn).unwrap();
+            let after = issue_tokens(USER.to_string(), None, &config, &mut conn).unwrap();
+
+            let before = verify_jwt_token(&before.access_token, &config).unwrap();
+            assert!(
+                is_token_revoked(before.token_uuid, USER, before.issued_at_us, &mut conn).unwrap()
+            );
+
+            let after = verify_jwt_token(&after.access_token, &config).unwrap();
+            assert!(
+                !is_token_revoked(after.token_uuid, USER, after.issued_at_us, &mut conn).unwrap()
+            );
+        }
+    }
+}
@@ -5,6 +5,29 @@ diesel::table! {
         id -> Uuid,
         created_at -> Timestamp,
         blog_id -> Uuid,
+        #[max_length = 254]
+        user -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    login_failures (scope, subject) {
+        #[max_length = 10]
+        scope -> Varchar,
+        #[max_length = 254]
+        subject -> Varchar,
+        failures -> Int4,
+        last_failure_at -> Timestamp,
+        locked_until -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    microblog_revisions (id) {
+        id -> Uuid,
+        blog_id -> Uuid,
+        blog_message -> Text,
+        created_at -> Timestamp,
     }
 }
 
@@ -13,12 +36,51 @@ diesel::table! {
         id -> Uuid,
         blog_message -> Text,
         created_at -> Timestamp,
+        #[max_length = 254]
+        author -> Nullable<Varchar>,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    password_reset_tokens (token_uuid) {
+        token_uuid -> Uuid,
+        #[max_length = 254]
+        user_email -> Varchar,
+        token_hash -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    refresh_tokens (token_uuid) {
+        token_uuid -> Uuid,
+        family_id -> Uuid,
+        #[max_length = 254]
+        user_email -> Varchar,
+        token_hash -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        rotated_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    revoked_tokens (token_uuid) {
+        token_uuid -> Uuid,
+        #[max_length = 254]
+        user_email -> Varchar,
+        revoked_at -> Timestamp,
+        expires_at -> Timestamp,
     }
 }
 
 diesel::table! {
     users (email) {
-        #[max_length = 50]
+        #[max_length = 254]
         email -> Varchar,
         #[max_length = 30]
         username -> Varchar,
@@ -27,11 +89,27 @@ diesel::table! {
         #[max_length = 10]
         contact -> Nullable<Varchar>,
         password -> Text,
+        #[max_length = 20]
+        role -> Varchar,
+        email_verified_at -> Nullable<Timestamp>,
+        tokens_invalid_before -> Nullable<Timestamp>,
     }
 }
 
+diesel::joinable!(likes -> microblogs (blog_id));
+diesel::joinable!(likes -> users (user));
+diesel::joinable!(microblog_revisions -> microblogs (blog_id));
+diesel::joinable!(microblogs -> users (author));
+diesel::joinable!(password_reset_tokens -> users (user_email));
+diesel::joinable!(refresh_tokens -> users (user_email));
+
 diesel::allow_tables_to_appear_in_same_query!(
     likes,
+    login_failures,
+    microblog_revisions,
     microblogs,
+    password_reset_tokens,
+    refresh_tokens,
+    revoked_tokens,
     users,
 );
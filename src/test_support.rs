@@ -0,0 +1,198 @@
+//! Helpers shared by the unit tests.
+
+use actix_web::body::MessageBody;
+use actix_web::cookie::Cookie;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{self, TestRequest};
+use actix_web::web::Data;
+use actix_web::App;
+use chrono::Utc;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Error as PoolError};
+use diesel::{Connection, PgConnection, RunQueryDsl};
+use dotenv::dotenv;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use serde_json::Value;
+use std::env;
+use uuid::Uuid;
+
+use crate::authorization::Role;
+use crate::config::Config;
+use crate::jwtAuth::{ACCESS_TOKEN_COOKIE, CSRF_TOKEN_COOKIE, CSRF_TOKEN_HEADER};
+use crate::lockout::LoginThrottle;
+use crate::mail::{self, MailTransport, Mailer};
+use crate::microblog::MicroBlog;
+use crate::schema::{microblogs, users};
+use crate::token::{self, KeyStore};
+use crate::user::{PasswordHash, UserDB};
+use crate::validation::{CommonPasswords, PasswordPolicy, BCRYPT_MAX_PASSWORD_BYTES};
+use crate::{routes, DBPool, DBPooledConnection};
+
+pub const TEST_KEY_ID: &str = "test";
+pub const TEST_SECRET: &[u8] = b"a test secret that is at least thirty-two bytes long";
+
+/// HS256 keys are the quickest to sign and verify with, so they are used
+/// unless a test is about the algorithm.
+pub fn hs256_keys(secret: &[u8]) -> KeyStore {
+    KeyStore::new(
+        Algorithm::HS256,
+        TEST_KEY_ID.to_string(),
+        EncodingKey::from_secret(secret),
+        DecodingKey::from_secret(secret),
+    )
+}
+
+/// A configuration with small TTLs and default policies, signing with
+/// `keys`.
+pub fn test_config(keys: KeyStore) -> Config {
+    Config {
+        database_url: env::var("DATABASE_URL").unwrap_or_default(),
+        bind_address: "127.0.0.1:8000".to_string(),
+        pool_size: 1,
+        log_level: "error".to_string(),
+        access_token_keys: keys,
+        access_token_max_age: 15,
+        refresh_token_max_age: 60,
+        token_issuer: "micro-blogging".to_string(),
+        token_audience: "micro-blogging".to_string(),
+        token_leeway: 60,
+        password_policy: PasswordPolicy {
+            min_length: 8,
+            max_length: BCRYPT_MAX_PASSWORD_BYTES,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            common_passwords: CommonPasswords::bundled(),
+        },
+        login_throttle: LoginThrottle {
+            max_account_failures: 5,
+            max_ip_failures: 20,
+            lockout_minutes: 15,
+        },
+        trust_proxy_headers: false,
+        mail_transport: MailTransport::Stdout,
+        mail_from: "Micro Blogging <no-reply@localhost>".to_string(),
+        public_url: "http://127.0.0.1:8000".to_string(),
+        email_verification_max_age: 24 * 60,
+        password_reset_max_age: 60,
+    }
+}
+
+/// Runs every connection inside a transaction that is never committed, so
+/// nothing a test writes outlives the test.
+#[derive(Debug)]
+struct TestTransaction;
+
+impl CustomizeConnection<PgConnection, PoolError> for TestTransaction {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), PoolError> {
+        conn.begin_test_transaction().map_err(PoolError::QueryError)
+    }
+}
+
+/// A pool holding a single connection to `DATABASE_URL`. The connection is
+/// reused by every request of the test, which therefore all see the same
+/// uncommitted data.
+pub fn test_pool() -> DBPool {
+    dotenv().ok();
+
+    let database_url =
+        env::var("DATABASE_URL").expect("DATABASE_URL must be set to run the database tests");
+
+    r2d2::Pool::builder()
+        .max_size(1)
+        .connection_customizer(Box::new(TestTransaction))
+        .build(ConnectionManager::<PgConnection>::new(database_url))
+        .expect("Failed to create pool")
+}
+
+/// The application as `main` builds it, minus the logger.
+pub fn test_app(
+    pool: &DBPool,
+    config: &Config,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let mailer: Data<dyn Mailer> = Data::from(mail::build_mailer(config).unwrap());
+
+    App::new()
+        .app_data(Data::new(pool.clone()))
+        .app_data(Data::new(config.clone()))
+        .app_data(mailer)
+        .configure(routes)
+}
+
+/// Stores a user with a verified email address. The password hash is not a
+/// real one, so the user cannot log in.
+pub fn create_user(email: &str, role: Role, conn: &mut DBPooledConnection) -> UserDB {
+    let user = UserDB {
+        email: email.to_string(),
+        username: email.split('@').next().unwrap().to_string(),
+        dateofbirth: None,
+        contact: None,
+        password: PasswordHash::from("not a bcrypt hash".to_string()),
+        role: role.as_str().to_string(),
+        email_verified_at: Some(Utc::now().naive_utc()),
+        tokens_invalid_before: None,
+    };
+
+    diesel::insert_into(users::table)
+        .values(user.clone())
+        .execute(conn)
+        .unwrap();
+
+    user
+}
+
+/// Stores a blog written by `author` and returns its id.
+pub fn create_blog(author: &str, conn: &mut DBPooledConnection) -> Uuid {
+    let blog = MicroBlog::new("Hello from the tests".to_string()).to_db_microblog(author);
+
+    diesel::insert_into(microblogs::table)
+        .values(&blog)
+        .execute(conn)
+        .unwrap();
+
+    blog.id
+}
+
+/// A fresh access token for `user`.
+pub fn access_token(user: &str, role: Role, config: &Config) -> String {
+    token::generate_jwt_token(user.to_string(), role, config)
+        .unwrap()
+        .access_token
+        .unwrap()
+}
+
+/// Sends `token` the way API clients do, in the `Authorization` header.
+pub fn bearer(req: TestRequest, token: &str) -> TestRequest {
+    req.insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+}
+
+/// Sends `token` the way browsers do, in the access token cookie, along with
+/// a matching CSRF token.
+pub fn cookie(req: TestRequest, token: &str) -> TestRequest {
+    req.cookie(Cookie::new(ACCESS_TOKEN_COOKIE, token.to_string()))
+        .cookie(Cookie::new(CSRF_TOKEN_COOKIE, "csrf"))
+        .insert_header((CSRF_TOKEN_HEADER, "csrf"))
+}
+
+/// Checks that the request was turned away with a 401 and an
+/// `ErrorResponse` body, which is returned for further checks.
+pub async fn assert_unauthorized(resp: ServiceResponse<impl MessageBody>) -> Value {
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["status"], "FAILED");
+    assert!(body["message"]
+        .as_str()
+        .is_some_and(|message| !message.is_empty()));
+
+    body
+}
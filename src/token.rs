@@ -1,77 +1,416 @@
 use base64::{engine::general_purpose, Engine as _};
-use chrono::Duration;
-use jsonwebtoken::errors::Error;
+use chrono::{Duration, Utc};
+use core::fmt;
+use jsonwebtoken::errors::{Error, ErrorKind};
 use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::str::FromStr;
 use uuid::Uuid;
 
+use crate::authorization::Role;
+use crate::config::Config;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Token {
     pub access_token: Option<String>,
     pub user: String,
     pub token_uuid: Uuid,
     pub expires_in: Option<i64>,
+    /// Microseconds since the epoch.
+    pub issued_at_us: i64,
+    pub role: Role,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenClaims {
     pub token_uuid: String,
-    pub user: String,
+    pub sub: String,
+    pub iss: String,
+    pub aud: String,
+    pub iat: i64,
+    /// `iat` in microseconds, the precision revocation cutoffs are stored
+    /// at, so a token can be told apart from a revocation in the same
+    /// second. Older tokens only carry `iat`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat_us: Option<i64>,
+    pub nbf: i64,
     pub exp: i64,
+    /// Tokens issued before roles existed carry no role and act as `user`.
+    #[serde(default)]
+    pub role: Role,
 }
 
-pub fn generate_jwt_token(user_id: String, ttl: i64, private_key: String) -> Result<Token, Error> {
-    let bytes_private_key = general_purpose::STANDARD.decode(private_key).unwrap();
-    let decoded_private_key = String::from_utf8(bytes_private_key).unwrap();
+/// Parsed signing and verification keys, built once at startup. Tokens are
+/// signed with the current key and carry its id in the `kid` header; any
+/// registered verification key is accepted, so a retired key keeps working
+/// until the tokens it signed have expired.
+#[derive(Clone)]
+pub struct KeyStore {
+    algorithm: Algorithm,
+    kid: String,
+    encoding_key: EncodingKey,
+    decoding_keys: HashMap<String, DecodingKey>,
+}
 
-    let now = chrono::Utc::now();
+impl KeyStore {
+    pub fn new(
+        algorithm: Algorithm,
+        kid: String,
+        encoding_key: EncodingKey,
+        decoding_key: DecodingKey,
+    ) -> Self {
+        let mut decoding_keys = HashMap::new();
+        decoding_keys.insert(kid.clone(), decoding_key);
+
+        KeyStore {
+            algorithm,
+            kid,
+            encoding_key,
+            decoding_keys,
+        }
+    }
+
+    /// Registers an additional key that tokens may still be signed with.
+    pub fn add_verification_key(&mut self, kid: String, decoding_key: DecodingKey) {
+        self.decoding_keys.insert(kid, decoding_key);
+    }
+
+    /// Tokens issued before keys had ids carry no `kid` and are checked
+    /// against the current key.
+    fn decoding_key(&self, kid: Option<&str>) -> Option<&DecodingKey> {
+        self.decoding_keys.get(kid.unwrap_or(&self.kid))
+    }
+}
+
+impl fmt::Debug for KeyStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyStore")
+            .field("algorithm", &self.algorithm)
+            .field("kid", &self.kid)
+            .field("verification_kids", &self.decoding_keys.keys())
+            .finish()
+    }
+}
+
+pub fn generate_jwt_token(user_id: String, role: Role, config: &Config) -> Result<Token, Error> {
+    let keys = &config.access_token_keys;
+    let now = Utc::now();
 
     let mut token = Token {
         access_token: None,
         user: user_id,
         token_uuid: Uuid::new_v4(),
-        expires_in: Some((now + Duration::minutes(ttl)).timestamp()),
+        expires_in: Some((now + Duration::minutes(config.access_token_max_age)).timestamp()),
+        issued_at_us: now.timestamp_micros(),
+        role,
     };
 
     let token_claims = TokenClaims {
         token_uuid: token.token_uuid.to_string(),
-        user: token.user.to_string(),
+        sub: token.user.to_string(),
+        iss: config.token_issuer.to_string(),
+        aud: config.token_audience.to_string(),
+        iat: now.timestamp(),
+        iat_us: Some(token.issued_at_us),
+        nbf: now.timestamp(),
         exp: token.expires_in.unwrap(),
+        role: token.role,
     };
 
-    let header = Header::new(Algorithm::RS256);
+    let mut header = Header::new(keys.algorithm);
+    header.kid = Some(keys.kid.clone());
 
-    let access_token = jsonwebtoken::encode(
-        &header,
-        &token_claims,
-        &EncodingKey::from_rsa_pem(decoded_private_key.as_bytes())?,
-    )?;
+    let access_token = jsonwebtoken::encode(&header, &token_claims, &keys.encoding_key)?;
 
     token.access_token = Some(access_token);
 
     Ok(token)
 }
 
-pub fn verify_jwt_token(token: &str, public_key: String) -> Result<Token, Error> {
-    let bytes_public_key = general_purpose::STANDARD.decode(public_key).unwrap();
-    let decoded_public_key = String::from_utf8(bytes_public_key).unwrap();
+/// Claims of tokens sent in links by email, such as email verification.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PurposeClaims {
+    pub sub: String,
+    pub iss: String,
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+/// Audience of a token issued for `purpose`. It differs from the access
+/// token audience, so these tokens are never accepted as access tokens and
+/// a token for one purpose cannot be used for another.
+fn purpose_audience(purpose: &str, config: &Config) -> String {
+    format!("{}:{}", config.token_audience, purpose)
+}
+
+/// Signs a token for `subject` that is only good for `purpose` and expires
+/// after `ttl` minutes.
+pub fn generate_purpose_token(
+    subject: &str,
+    purpose: &str,
+    ttl: i64,
+    config: &Config,
+) -> Result<String, Error> {
+    let keys = &config.access_token_keys;
+    let now = chrono::Utc::now();
+
+    let claims = PurposeClaims {
+        sub: subject.to_string(),
+        iss: config.token_issuer.to_string(),
+        aud: purpose_audience(purpose, config),
+        iat: now.timestamp(),
+        exp: (now + Duration::minutes(ttl)).timestamp(),
+    };
+
+    let mut header = Header::new(keys.algorithm);
+    header.kid = Some(keys.kid.clone());
+
+    jsonwebtoken::encode(&header, &claims, &keys.encoding_key)
+}
+
+/// Verifies a token from `generate_purpose_token` and returns its subject.
+pub fn verify_purpose_token(token: &str, purpose: &str, config: &Config) -> Result<String, Error> {
+    let keys = &config.access_token_keys;
+    let header = jsonwebtoken::decode_header(token)?;
+    let decoding_key = keys
+        .decoding_key(header.kid.as_deref())
+        .ok_or_else(|| Error::from(ErrorKind::InvalidToken))?;
+
+    let mut validation_algo = Validation::new(keys.algorithm);
+    validation_algo.set_issuer(&[&config.token_issuer]);
+    validation_algo.set_audience(&[purpose_audience(purpose, config)]);
+    validation_algo.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    validation_algo.leeway = config.token_leeway;
+
+    let decoded_token =
+        jsonwebtoken::decode::<PurposeClaims>(token, decoding_key, &validation_algo)?;
+
+    Ok(decoded_token.claims.sub)
+}
+
+/// Checks the signature and the standard claims. Tokens minted for another
+/// issuer or audience are rejected, as are tokens issued in the future;
+/// time based checks allow `config.token_leeway` seconds of clock skew.
+pub fn verify_jwt_token(token: &str, config: &Config) -> Result<Token, Error> {
+    let keys = &config.access_token_keys;
+    let header = jsonwebtoken::decode_header(token)?;
+    let decoding_key = keys
+        .decoding_key(header.kid.as_deref())
+        .ok_or_else(|| Error::from(ErrorKind::InvalidToken))?;
 
-    let validation_algo = Validation::new(Algorithm::RS256);
+    let mut validation_algo = Validation::new(keys.algorithm);
+    validation_algo.set_issuer(&[&config.token_issuer]);
+    validation_algo.set_audience(&[&config.token_audience]);
+    validation_algo.set_required_spec_claims(&["exp", "nbf", "iss", "aud", "sub"]);
+    validation_algo.validate_nbf = true;
+    validation_algo.leeway = config.token_leeway;
 
-    let decoded_token = jsonwebtoken::decode::<TokenClaims>(
-        token,
-        &DecodingKey::from_rsa_pem(decoded_public_key.as_bytes())?,
-        &validation_algo,
-    )?;
+    let decoded_token = jsonwebtoken::decode::<TokenClaims>(token, decoding_key, &validation_algo)?;
 
-    let user = decoded_token.claims.user;
-    let token_uuid = Uuid::from_str(decoded_token.claims.token_uuid.as_str()).unwrap();
+    if decoded_token.claims.iat > chrono::Utc::now().timestamp() + config.token_leeway as i64 {
+        return Err(Error::from(ErrorKind::ImmatureSignature));
+    }
+
+    let user = decoded_token.claims.sub;
+    let token_uuid = Uuid::from_str(decoded_token.claims.token_uuid.as_str())
+        .map_err(|_| Error::from(ErrorKind::InvalidToken))?;
 
     Ok(Token {
         access_token: None,
-        user: user,
-        token_uuid: token_uuid,
-        expires_in: None,
+        user,
+        token_uuid,
+        expires_in: Some(decoded_token.claims.exp),
+        issued_at_us: decoded_token
+            .claims
+            .iat_us
+            .unwrap_or(decoded_token.claims.iat * 1_000_000),
+        role: decoded_token.claims.role,
     })
 }
+
+/// Creates an opaque token of the form `<token_uuid>.<secret>`, as used for
+/// refresh and password reset tokens. The uuid is the key of its row.
+pub fn new_opaque_token() -> (Uuid, String) {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+
+    let token_uuid = Uuid::new_v4();
+    let token = format!(
+        "{}.{}",
+        token_uuid,
+        general_purpose::URL_SAFE_NO_PAD.encode(secret)
+    );
+
+    (token_uuid, token)
+}
+
+/// The row key of an opaque token, if it is well formed.
+pub fn opaque_token_uuid(token: &str) -> Option<Uuid> {
+    let (uuid_part, _) = token.split_once('.')?;
+    Uuid::from_str(uuid_part).ok()
+}
+
+/// Opaque tokens are only ever stored as a SHA-256 digest.
+pub fn hash_opaque_token(token: &str) -> String {
+    general_purpose::STANDARD.encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{hs256_keys, test_config, TEST_KEY_ID, TEST_SECRET};
+
+    fn rs256_keys(private_key: &str, public_key: &str) -> KeyStore {
+        KeyStore::new(
+            Algorithm::RS256,
+            TEST_KEY_ID.to_string(),
+            EncodingKey::from_rsa_pem(private_key.as_bytes()).unwrap(),
+            DecodingKey::from_rsa_pem(public_key.as_bytes()).unwrap(),
+        )
+    }
+
+    fn eddsa_keys(private_key: &str, public_key: &str) -> KeyStore {
+        KeyStore::new(
+            Algorithm::EdDSA,
+            TEST_KEY_ID.to_string(),
+            EncodingKey::from_ed_pem(private_key.as_bytes()).unwrap(),
+            DecodingKey::from_ed_pem(public_key.as_bytes()).unwrap(),
+        )
+    }
+
+    /// One key store per supported algorithm, all using the same key id.
+    fn key_stores() -> Vec<KeyStore> {
+        vec![
+            rs256_keys(
+                include_str!("test_keys/rsa_private.pem"),
+                include_str!("test_keys/rsa_public.pem"),
+            ),
+            eddsa_keys(
+                include_str!("test_keys/ed25519_private.pem"),
+                include_str!("test_keys/ed25519_public.pem"),
+            ),
+            hs256_keys(TEST_SECRET),
+        ]
+    }
+
+    /// The same algorithms as `key_stores`, with different keys.
+    fn other_key_stores() -> Vec<KeyStore> {
+        vec![
+            rs256_keys(
+                include_str!("test_keys/rsa_other_private.pem"),
+                include_str!("test_keys/rsa_other_public.pem"),
+            ),
+            eddsa_keys(
+                include_str!("test_keys/ed25519_other_private.pem"),
+                include_str!("test_keys/ed25519_other_public.pem"),
+            ),
+            hs256_keys(b"another test secret of at least thirty-two bytes"),
+        ]
+    }
+
+    fn sign(keys: KeyStore) -> String {
+        generate_jwt_token(
+            "alice@example.com".to_string(),
+            Role::Moderator,
+            &test_config(keys),
+        )
+        .unwrap()
+        .access_token
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trips_with_every_algorithm() {
+        for keys in key_stores() {
+            let algorithm = keys.algorithm;
+            let config = test_config(keys);
+
+            let token =
+                generate_jwt_token("alice@example.com".to_string(), Role::Moderator, &config)
+                    .unwrap();
+            let verified =
+                verify_jwt_token(token.access_token.as_deref().unwrap(), &config).unwrap();
+
+            assert_eq!(verified.user, "alice@example.com", "{:?}", algorithm);
+            assert_eq!(verified.token_uuid, token.token_uuid, "{:?}", algorithm);
+            assert_eq!(verified.issued_at_us, token.issued_at_us, "{:?}", algorithm);
+            assert_eq!(verified.expires_in, token.expires_in, "{:?}", algorithm);
+            assert_eq!(verified.role, Role::Moderator, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn rejects_tokens_signed_with_another_algorithm() {
+        for (signer, signing_keys) in key_stores().into_iter().enumerate() {
+            let signed_with = signing_keys.algorithm;
+            let token = sign(signing_keys);
+
+            for (verifier, keys) in key_stores().into_iter().enumerate() {
+                if signer == verifier {
+                    continue;
+                }
+
+                let verified_with = keys.algorithm;
+                let err = verify_jwt_token(&token, &test_config(keys)).unwrap_err();
+                assert_eq!(
+                    err.kind(),
+                    &ErrorKind::InvalidAlgorithm,
+                    "{:?} token verified with {:?}",
+                    signed_with,
+                    verified_with
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_tokens_signed_with_another_key() {
+        for (keys, other_keys) in key_stores().into_iter().zip(other_key_stores()) {
+            let algorithm = keys.algorithm;
+            let token = sign(other_keys);
+
+            let err = verify_jwt_token(&token, &test_config(keys)).unwrap_err();
+            assert_eq!(err.kind(), &ErrorKind::InvalidSignature, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn accepts_tokens_signed_with_a_retired_key() {
+        for (keys, old_keys) in key_stores().into_iter().zip(other_key_stores()) {
+            let algorithm = keys.algorithm;
+            let mut old_keys = old_keys;
+            old_keys.kid = "old".to_string();
+            let token = sign(old_keys);
+
+            let mut keys = keys;
+            let retired = other_key_stores()
+                .into_iter()
+                .find(|other| other.algorithm == algorithm)
+                .unwrap();
+            keys.add_verification_key(
+                "old".to_string(),
+                retired.decoding_keys[TEST_KEY_ID].clone(),
+            );
+
+            assert!(
+                verify_jwt_token(&token, &test_config(keys)).is_ok(),
+                "{:?}",
+                algorithm
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_tokens_with_an_unknown_key_id() {
+        let mut keys = hs256_keys(TEST_SECRET);
+        keys.kid = "unknown".to_string();
+        let token = sign(keys);
+
+        let err = verify_jwt_token(&token, &test_config(hs256_keys(TEST_SECRET))).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::InvalidToken);
+    }
+}
@@ -1,25 +1,40 @@
 use crate::{
-    response::StatusResponse,
-    token::generate_jwt_token,
-    validation::{validate_email, validate_password},
+    authorization::Role,
+    config::Config,
+    error::AppError,
+    jwtAuth::{auth_cookies, clear_auth_cookies, JWTAuthToken},
+    lockout::{check_login_allowed, clear_login_failures, record_login_failure, LoginScope},
+    mail::Mailer,
+    refresh_token::{issue_tokens, revoke_refresh_token_family, TokenResponse},
+    revocation::{revoke_access_token, revoke_all_user_tokens},
+    validation::{normalize_email, PasswordPolicy},
+    verification::send_verification_email,
     DBPool, DBPooledConnection,
 };
 
 use super::schema::users;
 use actix_web::{
-    post,
+    get, patch, post,
     web::{self, Data, Json},
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
 use bcrypt::{hash, verify, DEFAULT_COST};
-use diesel::{
-    result::Error::NotFound, ExpressionMethods, Insertable, QueryDsl, Queryable, RunQueryDsl,
-};
-use serde::{Deserialize, Serialize};
-use std::env;
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use diesel::{ExpressionMethods, Insertable, OptionalExtension, QueryDsl, Queryable, RunQueryDsl};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+
+static DUMMY_PASSWORD_HASH: OnceLock<PasswordHash> = OnceLock::new();
+
+/// Column limits of the users table.
+const MAX_USERNAME_LENGTH: usize = 30;
+const MAX_CONTACT_LENGTH: usize = 10;
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct User {
+/// Body of `POST /register`. It is only ever read from requests.
+#[derive(Debug, Deserialize)]
+pub struct RegisterRequest {
     email: String,
     name: String,
     dateofbirth: Option<String>,
@@ -27,180 +42,691 @@ pub struct User {
     password: String,
 }
 
-impl User {
-    pub fn new_user(&self) -> Self {
-        Self {
-            email: self.email.to_string(),
-            name: self.name.to_string(),
-            dateofbirth: self.dateofbirth.clone(),
-            contact: self.contact.clone(),
-            password: self.password.to_string(),
+impl RegisterRequest {
+    /// Trims the profile fields and returns every problem with them, using
+    /// the same rules as `PATCH /me`.
+    fn normalize(&mut self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        errors.extend(normalize_username(&mut self.name));
+        if let Some(dateofbirth) = &mut self.dateofbirth {
+            errors.extend(normalize_dateofbirth(dateofbirth));
+        }
+        if let Some(contact) = &mut self.contact {
+            errors.extend(normalize_contact(contact));
         }
+
+        errors
     }
 
-    pub fn to_user_db(&self) -> UserDB {
-        let hashed_password = hash(&self.password, DEFAULT_COST).unwrap();
-        UserDB {
+    pub fn to_user_db(&self) -> Result<UserDB, AppError> {
+        Ok(UserDB {
             email: self.email.to_string(),
             username: self.name.to_string(),
             dateofbirth: self.dateofbirth.clone(),
             contact: self.contact.clone(),
-            password: hashed_password,
-        }
+            password: PasswordHash::new(&self.password)?,
+            role: Role::User.as_str().to_string(),
+            email_verified_at: None,
+            tokens_invalid_before: None,
+        })
+    }
+}
+
+/// A bcrypt password hash. It does not implement `Serialize` and is left
+/// out of `Debug` output, so it cannot end up in a response body or a log.
+#[derive(Clone)]
+pub struct PasswordHash(String);
+
+impl PasswordHash {
+    pub fn new(password: &str) -> Result<Self, AppError> {
+        hash_password(password).map(Self)
+    }
+
+    pub fn matches(&self, password: &str) -> Result<bool, AppError> {
+        Ok(verify(password, &self.0)?)
+    }
+}
+
+impl fmt::Debug for PasswordHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PasswordHash(..)")
+    }
+}
+
+impl From<String> for PasswordHash {
+    fn from(hash: String) -> Self {
+        Self(hash)
     }
 }
 
+impl From<PasswordHash> for String {
+    fn from(hash: PasswordHash) -> Self {
+        hash.0
+    }
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
 #[diesel(table_name = users)]
-#[derive(Debug, Queryable, Insertable)]
 pub struct UserDB {
     pub email: String,
     pub username: String,
     pub dateofbirth: Option<String>,
     pub contact: Option<String>,
-    pub password: String,
+    #[diesel(deserialize_as = String, serialize_as = String)]
+    pub password: PasswordHash,
+    pub role: String,
+    pub email_verified_at: Option<NaiveDateTime>,
+    pub tokens_invalid_before: Option<NaiveDateTime>,
 }
 
 impl UserDB {
-    pub fn to_user(&self) -> User {
-        User {
+    pub fn to_public_user(&self) -> PublicUser {
+        PublicUser {
             email: self.email.to_string(),
-            name: self.username.to_string(),
+            username: self.username.to_string(),
             dateofbirth: self.dateofbirth.clone(),
             contact: self.contact.clone(),
-            password: self.password.to_string(),
+            role: self.role.parse().unwrap_or_default(),
+            email_verified: self.email_verified_at.is_some(),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Queryable)]
+/// What clients get to see of a user. It never carries the password hash.
+#[derive(Debug, Serialize)]
+pub struct PublicUser {
+    pub email: String,
+    pub username: String,
+    pub dateofbirth: Option<String>,
+    pub contact: Option<String>,
+    pub role: Role,
+    pub email_verified: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegisterResponse {
+    pub status: String,
+    pub message: String,
+    pub user: PublicUser,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogoutRequest {
+    pub refresh_token: Option<String>,
+}
+
+/// Fields of `PATCH /me`. Missing fields are left alone; `dateofbirth` and
+/// `contact` are cleared when set to `null`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateProfileRequest {
+    pub username: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub dateofbirth: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub contact: Option<Option<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// Tells a field sent as `null` apart from a missing one.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+impl UpdateProfileRequest {
+    fn is_empty(&self) -> bool {
+        self.username.is_none() && self.dateofbirth.is_none() && self.contact.is_none()
+    }
+
+    /// Trims the fields and returns every problem with them.
+    fn normalize(&mut self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if let Some(username) = &mut self.username {
+            errors.extend(normalize_username(username));
+        }
+        if let Some(Some(dateofbirth)) = &mut self.dateofbirth {
+            errors.extend(normalize_dateofbirth(dateofbirth));
+        }
+        if let Some(Some(contact)) = &mut self.contact {
+            errors.extend(normalize_contact(contact));
+        }
+
+        errors
+    }
+}
+
+/// Trims a username and returns the problem with it, if any.
+fn normalize_username(username: &mut String) -> Option<String> {
+    *username = username.trim().to_string();
+    if username.is_empty() {
+        Some("Username must not be empty".to_string())
+    } else if username.chars().count() > MAX_USERNAME_LENGTH {
+        Some(format!(
+            "Username must be at most {} characters long",
+            MAX_USERNAME_LENGTH
+        ))
+    } else {
+        None
+    }
+}
+
+/// Trims a date of birth and returns the problem with it, if any.
+fn normalize_dateofbirth(dateofbirth: &mut String) -> Option<String> {
+    *dateofbirth = dateofbirth.trim().to_string();
+    match NaiveDate::parse_from_str(dateofbirth, "%Y-%m-%d") {
+        Ok(date) if date > Utc::now().date_naive() => {
+            Some("Date of birth must not be in the future".to_string())
+        }
+        Ok(_) => None,
+        Err(_) => Some("Date of birth must be a date like 1990-01-31".to_string()),
+    }
+}
+
+/// Trims a contact number and returns the problem with it, if any.
+fn normalize_contact(contact: &mut String) -> Option<String> {
+    *contact = contact.trim().to_string();
+    if contact.is_empty()
+        || contact.len() > MAX_CONTACT_LENGTH
+        || !contact.chars().all(|c| c.is_ascii_digit())
+    {
+        Some(format!(
+            "Contact must be a phone number of at most {} digits",
+            MAX_CONTACT_LENGTH
+        ))
+    } else {
+        None
+    }
+}
+
+/// Body of `POST /login`. It is only ever read from requests.
+#[derive(Debug, Deserialize)]
 pub struct LoginUser {
     pub email: String,
     pub password: String,
 }
 
-pub fn register_user(user_data: User, conn: &mut DBPooledConnection) -> StatusResponse {
+pub fn register_user(
+    mut user_data: RegisterRequest,
+    password_policy: &PasswordPolicy,
+    conn: &mut DBPooledConnection,
+) -> Result<UserDB, AppError> {
     use crate::schema::users::dsl::*;
 
-    if !validate_email(&user_data.email) {
-        return StatusResponse {
-            status: "FAILED".to_string(),
-            message: "Invalid Email".to_string(),
-        };
+    user_data.email = normalize_email(&user_data.email)
+        .ok_or_else(|| AppError::Validation("Invalid Email".to_string()))?;
+
+    let errors = user_data.normalize();
+    if !errors.is_empty() {
+        return Err(AppError::ValidationErrors(
+            "Invalid profile".to_string(),
+            errors,
+        ));
     }
 
-    if !validate_password(&user_data.password) {
-        return StatusResponse {
-            status: "FAILED".to_string(),
-            message: "Password must be atleast 8 character long.".to_string(),
-        };
+    let violations =
+        password_policy.violations(&user_data.password, &user_data.email, &user_data.name);
+    if !violations.is_empty() {
+        return Err(AppError::ValidationErrors(
+            "Password does not meet the requirements".to_string(),
+            violations,
+        ));
     }
 
-    let user_data = user_data.to_user_db();
+    let user_data = user_data.to_user_db()?;
 
-    let _ = match users
-        .filter(email.eq(&user_data.email))
-        .first::<UserDB>(conn)
-    {
-        Ok(_) => {
-            return StatusResponse {
-                status: "FAILED".to_string(),
-                message: "User Already Exist".to_string(),
-            }
-        }
-        Err(NotFound) => match diesel::insert_into(users).values(user_data).execute(conn) {
-            Ok(_) => {
-                return StatusResponse {
-                    status: "SUCCESS".to_string(),
-                    message: "User Registered Successfully".to_string(),
-                }
-            }
-            Err(err) => {
-                return StatusResponse {
-                    status: "FAILED".to_string(),
-                    message: format!("Error while inserting data, {}", err),
-                }
-            }
-        },
-        Err(err) => {
-            return StatusResponse {
-                status: "FAILED".to_string(),
-                message: format!("Error while checking for existing user: {}", err),
-            }
-        }
-    };
+    let user_exists = diesel::select(diesel::dsl::exists(
+        users.filter(email.eq(&user_data.email)),
+    ))
+    .get_result::<bool>(conn)?;
+
+    if user_exists {
+        return Err(AppError::Conflict("User Already Exist".to_string()));
+    }
+
+    diesel::insert_into(users)
+        .values(user_data.clone())
+        .execute(conn)
+        .map_err(|err| match AppError::from(err) {
+            AppError::Conflict(_) => AppError::Conflict("User Already Exist".to_string()),
+            err => err,
+        })?;
+
+    Ok(user_data)
+}
+
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    Ok(hash(password, DEFAULT_COST)?)
+}
+
+/// Hash checked against when the email is unknown, so that a login takes
+/// as long whether or not the account exists.
+fn dummy_password_hash() -> &'static PasswordHash {
+    DUMMY_PASSWORD_HASH.get_or_init(|| {
+        PasswordHash::new("dummy password").unwrap_or_else(|_| PasswordHash::from(String::new()))
+    })
 }
 
-pub fn login_user(login_data: LoginUser, conn: &mut DBPooledConnection) -> StatusResponse {
+/// Checks the credentials and issues tokens. Failures are counted against
+/// the email and the client address, which are throttled and eventually
+/// locked out; the response never tells which part was wrong.
+pub fn login_user(
+    login_data: LoginUser,
+    client_ip: Option<String>,
+    config: &Config,
+    conn: &mut DBPooledConnection,
+) -> Result<TokenResponse, AppError> {
     use crate::schema::users::dsl::*;
 
-    if !validate_email(&login_data.email) {
-        return StatusResponse {
-            status: "FAILED".to_string(),
-            message: "Invalid Email".to_string(),
-        };
+    let login_email = normalize_email(&login_data.email)
+        .ok_or_else(|| AppError::Validation("Invalid Email".to_string()))?;
+    let throttle = &config.login_throttle;
+
+    check_login_allowed(LoginScope::Account, &login_email, throttle, conn)?;
+    if let Some(client_ip) = &client_ip {
+        check_login_allowed(LoginScope::Ip, client_ip, throttle, conn)?;
     }
 
-    let data = match users
-        .filter(email.eq(&login_data.email))
-        .select((email, password))
-        .first::<LoginUser>(conn)
-    {
-        Ok(res) => res,
-        Err(NotFound) => {
-            return StatusResponse {
-                status: "FAILED".to_string(),
-                message: "No user found with given email".to_string(),
-            };
-        }
-        Err(err) => {
-            return StatusResponse {
-                status: "FAILED".to_string(),
-                message: format!("Error while checking existing database, {}", err),
-            }
+    let stored_hash = users
+        .find(&login_email)
+        .select(password)
+        .first::<String>(conn)
+        .optional()?
+        .map(PasswordHash::from);
+
+    let verify_auth = match &stored_hash {
+        Some(stored_hash) => stored_hash.matches(&login_data.password)?,
+        None => {
+            let _ = dummy_password_hash().matches(&login_data.password);
+            false
         }
     };
 
-    let verify_auth = verify(&login_data.password, &data.password).unwrap();
     if !verify_auth {
-        return StatusResponse {
-            status: "FAILED".to_string(),
-            message: "Invalid Password".to_string(),
-        };
+        record_login_failure(LoginScope::Account, &login_email, throttle, conn)?;
+        if let Some(client_ip) = &client_ip {
+            record_login_failure(LoginScope::Ip, client_ip, throttle, conn)?;
+        }
+
+        return Err(AppError::Unauthorized("Invalid credentials".to_string()));
     }
 
-    let ttl = env::var("ACCESS_TOKEN_MAX_AGE").expect("Failed to fetch env variable.");
-    let ttl = ttl.parse::<i64>().unwrap();
-    let private_key =
-        env::var("BASE64_ACCESS_TOKEN_PRIVATE_KEY").expect("failed to fetch private key");
-    let token = generate_jwt_token(login_data.email, ttl, private_key).unwrap();
+    clear_login_failures(LoginScope::Account, &login_email, conn)?;
 
-    StatusResponse {
-        status: "SUCCESS".to_string(),
-        message: token.access_token.unwrap(),
+    issue_tokens(login_email, None, config, conn)
+}
+
+fn find_user(user: &str, conn: &mut DBPooledConnection) -> Result<UserDB, AppError> {
+    use crate::schema::users::dsl::*;
+
+    users
+        .find(user)
+        .first::<UserDB>(conn)
+        .optional()?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))
+}
+
+fn update_profile(
+    user: &str,
+    mut changes: UpdateProfileRequest,
+    conn: &mut DBPooledConnection,
+) -> Result<UserDB, AppError> {
+    use crate::schema::users::dsl::*;
+
+    let errors = changes.normalize();
+    if !errors.is_empty() {
+        return Err(AppError::ValidationErrors(
+            "Invalid profile".to_string(),
+            errors,
+        ));
+    }
+
+    if changes.is_empty() {
+        return find_user(user, conn);
+    }
+
+    diesel::update(users.find(user))
+        .set((
+            changes.username.map(|value| username.eq(value)),
+            changes.dateofbirth.map(|value| dateofbirth.eq(value)),
+            changes.contact.map(|value| contact.eq(value)),
+        ))
+        .get_result::<UserDB>(conn)
+        .optional()?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))
+}
+
+/// Replaces the password of `user` after checking the current one. Wrong
+/// guesses count as failed logins. Every session is logged out and a new
+/// token pair is issued for the caller.
+fn change_password(
+    user: String,
+    data: ChangePasswordRequest,
+    config: &Config,
+    conn: &mut DBPooledConnection,
+) -> Result<TokenResponse, AppError> {
+    use crate::schema::users::dsl::*;
+
+    let throttle = &config.login_throttle;
+    check_login_allowed(LoginScope::Account, &user, throttle, conn)?;
+
+    let current = find_user(&user, conn)?;
+    if !current.password.matches(&data.current_password)? {
+        record_login_failure(LoginScope::Account, &user, throttle, conn)?;
+        return Err(AppError::Forbidden(
+            "Current password is incorrect".to_string(),
+        ));
+    }
+
+    let violations =
+        config
+            .password_policy
+            .violations(&data.new_password, &current.email, &current.username);
+    if !violations.is_empty() {
+        return Err(AppError::ValidationErrors(
+            "Password does not meet the requirements".to_string(),
+            violations,
+        ));
     }
+
+    diesel::update(users.find(&user))
+        .set(password.eq(hash_password(&data.new_password)?))
+        .execute(conn)?;
+
+    revoke_all_user_tokens(&user, conn)?;
+    clear_login_failures(LoginScope::Account, &user, conn)?;
+
+    issue_tokens(user, None, config, conn)
 }
 
+/// Creates the account and mails a verification link. The account exists
+/// even if the mail cannot be sent; the user can ask for a new link with
+/// `POST /verify-email/resend`.
 #[post("/register")]
-async fn register(data: Json<User>, pool: Data<DBPool>) -> HttpResponse {
-    let mut conn = pool.get().expect("Cannot establish connection to pool");
+async fn register(
+    data: Json<RegisterRequest>,
+    config: Data<Config>,
+    mailer: Data<dyn Mailer>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
+
+    let user = web::block(move || {
+        let user = register_user(data.into_inner(), &config.password_policy, &mut conn)?;
+
+        if let Err(err) = send_verification_email(&user.email, &config, mailer.as_ref()) {
+            println!("Failed to send verification email: {}", err);
+        }
+
+        Ok::<_, AppError>(user)
+    })
+    .await??;
 
-    let res = web::block(move || register_user(data.new_user(), &mut conn))
-        .await
-        .unwrap();
+    Ok(HttpResponse::Created().json(RegisterResponse {
+        status: "SUCCESS".to_string(),
+        message: "User Registered Successfully, please verify your email address".to_string(),
+        user: user.to_public_user(),
+    }))
+}
+
+/// The address failed logins are counted against. Behind a reverse proxy
+/// every connection comes from the proxy, so with `TRUST_PROXY_HEADERS` the
+/// address the proxy forwarded is used instead, falling back to the
+/// connection's when it is not a valid IP address.
+fn client_ip(req: &HttpRequest, config: &Config) -> Option<String> {
+    let peer_ip = req.peer_addr().map(|addr| addr.ip());
+
+    if !config.trust_proxy_headers {
+        return peer_ip.map(|ip| ip.to_string());
+    }
+
+    let connection_info = req.connection_info();
+    let forwarded = connection_info.realip_remote_addr().and_then(|addr| {
+        addr.parse::<IpAddr>()
+            .or_else(|_| addr.parse::<SocketAddr>().map(|addr| addr.ip()))
+            .ok()
+    });
 
-    HttpResponse::Ok().json(res)
+    forwarded.or(peer_ip).map(|ip| ip.to_string())
 }
 
 #[post("/login")]
-async fn login(data: Json<LoginUser>, pool: Data<DBPool>) -> HttpResponse {
-    let mut conn = pool.get().expect("Cannot establish connection to pool");
+async fn login(
+    req: HttpRequest,
+    data: Json<LoginUser>,
+    config: Data<Config>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
+
+    let login_data = data.into_inner();
+    let client_ip = client_ip(&req, &config);
+    let ttl = config.access_token_max_age;
+
+    let res = web::block(move || login_user(login_data, client_ip, &config, &mut conn)).await??;
+    let (access_cookie, csrf_cookie) = auth_cookies(&res.access_token, ttl);
+
+    Ok(HttpResponse::Ok()
+        .cookie(access_cookie)
+        .cookie(csrf_cookie)
+        .json(res))
+}
+
+/// Revokes the access token used for this request and, when given, the
+/// refresh token family it was issued with.
+#[post("/logout")]
+async fn logout(
+    auth: JWTAuthToken,
+    data: Option<Json<LogoutRequest>>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
 
-    let temp_data = serde_json::to_string(&data).unwrap();
-    let login_data: LoginUser = serde_json::from_str(&temp_data).unwrap();
+    let refresh_token = data.and_then(|data| data.into_inner().refresh_token);
 
-    let res = web::block(move || login_user(login_data, &mut conn))
-        .await
-        .unwrap();
+    web::block(move || {
+        revoke_access_token(auth.token_uuid, &auth.user, auth.expires_in, &mut conn)?;
 
-    HttpResponse::Ok().json(res)
+        match refresh_token {
+            Some(refresh_token) => {
+                revoke_refresh_token_family(&refresh_token, &auth.user, &mut conn)
+            }
+            None => Ok(()),
+        }
+    })
+    .await??;
+
+    let (access_cookie, csrf_cookie) = clear_auth_cookies();
+
+    Ok(HttpResponse::NoContent()
+        .cookie(access_cookie)
+        .cookie(csrf_cookie)
+        .finish())
+}
+
+#[get("/me")]
+async fn me(auth: JWTAuthToken, pool: Data<DBPool>) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
+
+    let user = web::block(move || find_user(&auth.user, &mut conn)).await??;
+
+    Ok(HttpResponse::Ok().json(user.to_public_user()))
+}
+
+#[patch("/me")]
+async fn update_me(
+    auth: JWTAuthToken,
+    data: Json<UpdateProfileRequest>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
+
+    let user =
+        web::block(move || update_profile(&auth.user, data.into_inner(), &mut conn)).await??;
+
+    Ok(HttpResponse::Ok().json(user.to_public_user()))
+}
+
+#[post("/me/password")]
+async fn update_password(
+    auth: JWTAuthToken,
+    data: Json<ChangePasswordRequest>,
+    config: Data<Config>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
+
+    let ttl = config.access_token_max_age;
+    let res = web::block(move || change_password(auth.user, data.into_inner(), &config, &mut conn))
+        .await??;
+    let (access_cookie, csrf_cookie) = auth_cookies(&res.access_token, ttl);
+
+    Ok(HttpResponse::Ok()
+        .cookie(access_cookie)
+        .cookie(csrf_cookie)
+        .json(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use diesel::{QueryDsl, RunQueryDsl};
+    use serde_json::{json, Value};
+
+    use super::client_ip;
+    use crate::authorization::Role;
+    use crate::schema::users;
+    use crate::test_support::{
+        access_token, bearer, create_user, hs256_keys, test_app, test_config, test_pool,
+        TEST_SECRET,
+    };
+
+    const ADMIN: &str = "admin@example.com";
+    const USER: &str = "hash-check@example.com";
+
+    /// Prefixes bcrypt hashes start with, whichever variant wrote them.
+    const BCRYPT_PREFIXES: [&str; 3] = ["$2b$", "$2a$", "$2y$"];
+
+    fn assert_no_password_hash(body: &[u8], endpoint: &str) {
+        let body = String::from_utf8_lossy(body);
+        for prefix in BCRYPT_PREFIXES {
+            assert!(
+                !body.contains(prefix),
+                "{} leaked a password hash: {}",
+                endpoint,
+                body
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn responses_never_contain_password_hashes() {
+        let pool = test_pool();
+        let config = test_config(hs256_keys(TEST_SECRET));
+        create_user(ADMIN, Role::Admin, &mut pool.get().unwrap());
+        let app = test::init_service(test_app(&pool, &config)).await;
+
+        let req = TestRequest::post()
+            .uri("/register")
+            .set_json(json!({
+                "email": USER,
+                "name": "hashcheck",
+                "password": "correct horse battery staple",
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_no_password_hash(&test::read_body(resp).await, "POST /register");
+
+        // The checks below are only meaningful if a real hash is stored.
+        let stored = users::table
+            .find(USER)
+            .select(users::password)
+            .first::<String>(&mut pool.get().unwrap())
+            .unwrap();
+        assert!(BCRYPT_PREFIXES
+            .iter()
+            .any(|prefix| stored.starts_with(prefix)));
+
+        let token = access_token(USER, Role::User, &config);
+        let admin_token = access_token(ADMIN, Role::Admin, &config);
+
+        for (endpoint, req) in [
+            ("GET /me", bearer(TestRequest::get(), &token).uri("/me")),
+            (
+                "PATCH /me",
+                bearer(TestRequest::patch(), &token)
+                    .uri("/me")
+                    .set_json(json!({ "username": "hashcheck2" })),
+            ),
+            (
+                "GET /admin/users",
+                bearer(TestRequest::get(), &admin_token).uri("/admin/users"),
+            ),
+        ] {
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{}", endpoint);
+            assert_no_password_hash(&test::read_body(resp).await, endpoint);
+        }
+    }
+
+    #[actix_web::test]
+    async fn register_rejects_profiles_that_do_not_fit_the_columns() {
+        let pool = test_pool();
+        let config = test_config(hs256_keys(TEST_SECRET));
+        let app = test::init_service(test_app(&pool, &config)).await;
+
+        let req = TestRequest::post()
+            .uri("/register")
+            .set_json(json!({
+                "email": USER,
+                "name": "a".repeat(31),
+                "dateofbirth": "01/31/1990",
+                "contact": "12345678901",
+                "password": "correct horse battery staple",
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["message"], "Invalid profile");
+        assert_eq!(body["errors"].as_array().map(Vec::len), Some(3));
+
+        let user_exists = diesel::select(diesel::dsl::exists(users::table.find(USER)))
+            .get_result::<bool>(&mut pool.get().unwrap())
+            .unwrap();
+        assert!(!user_exists);
+    }
+
+    #[test]
+    fn takes_the_client_ip_from_proxy_headers_only_when_trusted() {
+        let mut config = test_config(hs256_keys(TEST_SECRET));
+        let request = |forwarded_for: &str| {
+            TestRequest::default()
+                .peer_addr("10.0.0.1:4321".parse().unwrap())
+                .insert_header(("X-Forwarded-For", forwarded_for.to_string()))
+                .to_http_request()
+        };
+
+        let req = request("203.0.113.7, 10.0.0.1");
+        assert_eq!(client_ip(&req, &config).as_deref(), Some("10.0.0.1"));
+
+        config.trust_proxy_headers = true;
+        assert_eq!(client_ip(&req, &config).as_deref(), Some("203.0.113.7"));
+
+        let req = request("not an address");
+        assert_eq!(client_ip(&req, &config).as_deref(), Some("10.0.0.1"));
+    }
 }
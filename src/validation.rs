@@ -1,12 +1,308 @@
 use regex::Regex;
+use std::collections::HashSet;
+use std::sync::{Arc, OnceLock};
+use std::{fmt, fs, io};
 
-// r"^[\w-\.]+@([\w-]+\.)+[\w-]{2,4}$ -> email
-pub fn validate_email(email: &str) -> bool {
-    let email_regex = Regex::new(r"^([\w-]+[\.]*)+[\w-]+@([\w-]+\.)+[\w-]{2,4}$").unwrap();
-    email_regex.is_match(email)
+/// bcrypt only looks at the first 72 bytes of a password.
+pub const BCRYPT_MAX_PASSWORD_BYTES: usize = 72;
+
+/// Limits from RFC 5321 section 4.5.3.1.
+const MAX_EMAIL_LENGTH: usize = 254;
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+const MAX_DOMAIN_LENGTH: usize = 253;
+
+static LOCAL_PART_REGEX: OnceLock<Regex> = OnceLock::new();
+static DOMAIN_LABEL_REGEX: OnceLock<Regex> = OnceLock::new();
+
+/// Validates an address and returns the form it is stored and looked up in:
+/// trimmed, lowercased, with an internationalized domain converted to
+/// punycode. The local part must be an RFC 5321 dot-string; quoted local
+/// parts are not accepted.
+pub fn normalize_email(email: &str) -> Option<String> {
+    let (local_part, domain) = email.trim().rsplit_once('@')?;
+
+    let local_part = local_part.to_lowercase();
+    let local_part_regex = LOCAL_PART_REGEX.get_or_init(|| {
+        Regex::new(r"^[a-z0-9!#$%&'*+/=?^_`{|}~-]+(\.[a-z0-9!#$%&'*+/=?^_`{|}~-]+)*$").unwrap()
+    });
+    if local_part.len() > MAX_LOCAL_PART_LENGTH || !local_part_regex.is_match(&local_part) {
+        return None;
+    }
+
+    // `domain_to_ascii` also lowercases and applies Unicode normalization.
+    let domain = idna::domain_to_ascii(domain).ok()?;
+    let domain_label_regex = DOMAIN_LABEL_REGEX
+        .get_or_init(|| Regex::new(r"^[a-z0-9]([a-z0-9-]{0,61}[a-z0-9])?$").unwrap());
+    let labels = domain.split('.').collect::<Vec<_>>();
+    if domain.len() > MAX_DOMAIN_LENGTH
+        || labels.len() < 2
+        || !labels
+            .iter()
+            .all(|label| domain_label_regex.is_match(label))
+        || labels.last()?.chars().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let email = format!("{}@{}", local_part, domain);
+    (email.len() <= MAX_EMAIL_LENGTH).then_some(email)
+}
+
+/// Passwords too common to be accepted, compared case-insensitively. The
+/// bundled list only has entries long enough to pass the default minimum
+/// length; a bigger one can be added with `COMMON_PASSWORDS_FILE`.
+#[derive(Clone)]
+pub struct CommonPasswords(Arc<HashSet<String>>);
+
+impl CommonPasswords {
+    pub fn bundled() -> Self {
+        CommonPasswords(Arc::new(parse_password_list(include_str!(
+            "common_passwords.txt"
+        ))))
+    }
+
+    /// The bundled list plus the passwords in `path`, one per line.
+    pub fn with_file(path: &str) -> io::Result<Self> {
+        let mut passwords = parse_password_list(include_str!("common_passwords.txt"));
+        passwords.extend(parse_password_list(&fs::read_to_string(path)?));
+
+        Ok(CommonPasswords(Arc::new(passwords)))
+    }
+
+    fn contains(&self, password: &str) -> bool {
+        self.0.contains(&password.to_lowercase())
+    }
+}
+
+impl fmt::Debug for CommonPasswords {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CommonPasswords({} entries)", self.0.len())
+    }
+}
+
+fn parse_password_list(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Rules a new password has to satisfy, configured at startup.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    pub common_passwords: CommonPasswords,
+}
+
+impl PasswordPolicy {
+    /// Checks `password` against every rule and returns all the rules it
+    /// breaks, so the user can fix them in one go. `email` and `username`
+    /// belong to the account the password is for.
+    pub fn violations(&self, password: &str, email: &str, username: &str) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            violations.push(format!(
+                "Password must be at least {} characters long",
+                self.min_length
+            ));
+        }
+        if password.len() > self.max_length {
+            violations.push(format!(
+                "Password must be at most {} bytes long",
+                self.max_length
+            ));
+        }
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            violations.push("Password must contain a lowercase letter".to_string());
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            violations.push("Password must contain an uppercase letter".to_string());
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            violations.push("Password must contain a digit".to_string());
+        }
+        if self.require_symbol && password.chars().all(char::is_alphanumeric) {
+            violations.push("Password must contain a symbol".to_string());
+        }
+
+        let lowercase = password.to_lowercase();
+        let local_part = email.split('@').next().unwrap_or_default();
+        if [email, local_part, username]
+            .iter()
+            .any(|identity| !identity.is_empty() && lowercase == identity.to_lowercase())
+        {
+            violations.push("Password must not be the same as your email or username".to_string());
+        }
+        if self.common_passwords.contains(password) {
+            violations.push("Password is too common".to_string());
+        }
+
+        violations
+    }
 }
 
-pub fn validate_password(password: &str) -> bool {
-    // let password_regex = Regex::new(r"^[[\w-][@!#$\.&]+]{8,}$").unwrap();
-    password.len() >= 8
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strict_policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 12,
+            max_length: BCRYPT_MAX_PASSWORD_BYTES,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+            common_passwords: CommonPasswords::bundled(),
+        }
+    }
+
+    #[test]
+    fn normalizes_valid_emails() {
+        let cases = [
+            ("foo@x.com", "foo@x.com"),
+            ("Foo@x.com", "foo@x.com"),
+            ("FOO@X.COM", "foo@x.com"),
+            ("  foo@x.com \n", "foo@x.com"),
+            ("first.last+tag@example.com", "first.last+tag@example.com"),
+            ("o'brien@example.co.uk", "o'brien@example.co.uk"),
+            ("curator@collection.museum", "curator@collection.museum"),
+            ("dev@startup.technology", "dev@startup.technology"),
+            ("info@bücher.de", "info@xn--bcher-kva.de"),
+            ("Info@BÜCHER.de", "info@xn--bcher-kva.de"),
+            ("admin@例え.jp", "admin@xn--r8jz45g.jp"),
+            ("user@xn--bcher-kva.de", "user@xn--bcher-kva.de"),
+            ("user@sub-domain.example.com", "user@sub-domain.example.com"),
+        ];
+
+        for (email, normalized) in cases {
+            assert_eq!(
+                normalize_email(email).as_deref(),
+                Some(normalized),
+                "{}",
+                email
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_emails() {
+        let long_local_part = format!("{}@example.com", "a".repeat(65));
+        let long_label = format!("user@{}.com", "a".repeat(64));
+        let long_domain = format!("user@{}.com", vec!["a".repeat(63); 4].join("."));
+        let long_email = format!(
+            "{}@{}.com",
+            "a".repeat(64),
+            vec!["a".repeat(60); 4].join(".")
+        );
+
+        let cases = [
+            "",
+            "foo",
+            "foo@",
+            "@x.com",
+            "foo@x",
+            "foo@.com",
+            "foo@x..com",
+            "foo@-x.com",
+            "foo@x-.com",
+            "foo@x.123",
+            "foo@127.0.0.1",
+            "foo bar@x.com",
+            ".foo@x.com",
+            "foo.@x.com",
+            "foo..bar@x.com",
+            "\"foo\"@x.com",
+            "josé@x.com",
+            "foo@x_y.com",
+            long_local_part.as_str(),
+            long_label.as_str(),
+            long_domain.as_str(),
+            long_email.as_str(),
+        ];
+
+        for email in cases {
+            assert_eq!(normalize_email(email), None, "{}", email);
+        }
+    }
+
+    #[test]
+    fn reports_every_password_violation_at_once() {
+        let violations = strict_policy().violations("password", "alice@example.com", "alice");
+
+        assert_eq!(
+            violations,
+            [
+                "Password must be at least 12 characters long",
+                "Password must contain an uppercase letter",
+                "Password must contain a digit",
+                "Password must contain a symbol",
+                "Password is too common",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_passwords_matching_the_account() {
+        let policy = PasswordPolicy {
+            min_length: 1,
+            ..strict_policy()
+        };
+
+        for password in ["Alice@Example.com", "ALICE", "Wonderland"] {
+            let violations = policy.violations(password, "alice@example.com", "wonderland");
+            assert!(
+                violations.contains(
+                    &"Password must not be the same as your email or username".to_string()
+                ),
+                "{}",
+                password
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_passwords_bcrypt_would_truncate() {
+        let password = format!("Aa1!{}", "é".repeat(35));
+        assert!(password.chars().count() < BCRYPT_MAX_PASSWORD_BYTES);
+
+        let violations = strict_policy().violations(&password, "alice@example.com", "alice");
+        assert_eq!(violations, ["Password must be at most 72 bytes long"]);
+    }
+
+    #[test]
+    fn accepts_passwords_that_meet_every_rule() {
+        let violations =
+            strict_policy().violations("Correct-Horse-42-Staple", "alice@example.com", "alice");
+        assert!(violations.is_empty(), "{:?}", violations);
+    }
+
+    #[test]
+    fn compares_common_passwords_case_insensitively() {
+        let policy = PasswordPolicy {
+            min_length: 8,
+            max_length: BCRYPT_MAX_PASSWORD_BYTES,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            common_passwords: CommonPasswords::bundled(),
+        };
+
+        for password in ["password1", "PASSWORD1", "QwertyUiop"] {
+            assert_eq!(
+                policy.violations(password, "alice@example.com", "alice"),
+                ["Password is too common"],
+                "{}",
+                password
+            );
+        }
+    }
 }
@@ -0,0 +1,132 @@
+use actix_web::{
+    get, post,
+    web::{self, Data, Query},
+    HttpResponse,
+};
+use chrono::Utc;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::error::AppError;
+use crate::jwtAuth::JWTAuthToken;
+use crate::mail::{Email, Mailer};
+use crate::response::StatusResponse;
+use crate::token::{generate_purpose_token, verify_purpose_token};
+use crate::{DBPool, DBPooledConnection};
+
+const VERIFY_EMAIL_PURPOSE: &str = "verify-email";
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+/// Mails `email` a link to `GET /verify-email` with a signed token.
+pub fn send_verification_email(
+    email: &str,
+    config: &Config,
+    mailer: &dyn Mailer,
+) -> Result<(), AppError> {
+    let token = generate_purpose_token(
+        email,
+        VERIFY_EMAIL_PURPOSE,
+        config.email_verification_max_age,
+        config,
+    )?;
+
+    mailer.send(&Email {
+        to: email.to_string(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Open this link to verify your email address:\n\n{}/verify-email?token={}\n\nThe link expires in {} minutes.",
+            config.public_url, token, config.email_verification_max_age
+        ),
+    })
+}
+
+fn is_email_verified(user: &str, conn: &mut DBPooledConnection) -> Result<bool, AppError> {
+    use crate::schema::users::dsl::*;
+
+    let verified = users
+        .find(user)
+        .select(email_verified_at.is_not_null())
+        .first::<bool>(conn)
+        .optional()?;
+
+    Ok(verified.unwrap_or(false))
+}
+
+/// Checks that `user` has verified their email address.
+pub fn require_verified_email(user: &str, conn: &mut DBPooledConnection) -> Result<(), AppError> {
+    if is_email_verified(user, conn)? {
+        Ok(())
+    } else {
+        Err(AppError::Forbidden(
+            "Please verify your email address first".to_string(),
+        ))
+    }
+}
+
+/// Marks the address in the token as verified. A token only works while the
+/// address is unverified, so each one can be used once.
+fn verify_email(
+    token: &str,
+    config: &Config,
+    conn: &mut DBPooledConnection,
+) -> Result<(), AppError> {
+    use crate::schema::users::dsl::*;
+
+    let invalid_token =
+        || AppError::Validation("Invalid or expired verification token".to_string());
+
+    let user =
+        verify_purpose_token(token, VERIFY_EMAIL_PURPOSE, config).map_err(|_| invalid_token())?;
+
+    let updated = diesel::update(users.find(&user).filter(email_verified_at.is_null()))
+        .set(email_verified_at.eq(Utc::now().naive_utc()))
+        .execute(conn)?;
+
+    match updated {
+        0 => Err(invalid_token()),
+        _ => Ok(()),
+    }
+}
+
+#[get("/verify-email")]
+async fn verify(
+    query: Query<VerifyEmailQuery>,
+    config: Data<Config>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
+
+    web::block(move || verify_email(&query.token, &config, &mut conn)).await??;
+
+    Ok(HttpResponse::Ok().json(StatusResponse {
+        status: "SUCCESS".to_string(),
+        message: "Email verified".to_string(),
+    }))
+}
+
+/// Sends a new verification link to the logged in user.
+#[post("/verify-email/resend")]
+async fn resend(
+    auth: JWTAuthToken,
+    config: Data<Config>,
+    mailer: Data<dyn Mailer>,
+    pool: Data<DBPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut conn = pool.get()?;
+
+    web::block(move || {
+        if is_email_verified(&auth.user, &mut conn)? {
+            return Err(AppError::Conflict("Email is already verified".to_string()));
+        }
+
+        send_verification_email(&auth.user, &config, mailer.as_ref())
+    })
+    .await??;
+
+    Ok(HttpResponse::Accepted().finish())
+}